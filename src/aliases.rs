@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// Longest alias we take, in characters
+const MAX_ALIAS_LENGTH: usize = 32;
+// Aliases a chat can have, so nobody squats on all the good names
+pub const MAX_ALIASES_PER_CHAT: usize = 10;
+// Words with their own meaning after /alias, they can't be aliases
+const RESERVED: &[&str] = &["sterge"];
+
+// The names the chats are known by. A chat may have more aliases, but an alias belongs to
+// a single chat. The aliases are kept lowercase, so the case doesn't matter anywhere
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Aliases {
+    chats: HashMap<String, i64>,
+}
+
+pub fn normalize(alias: &str) -> String {
+    alias.trim().to_lowercase()
+}
+
+// The alias as it will be kept, if it's a valid one: letters, digits, '_' and '-', and
+// not something that could be taken for the id of a chat
+pub fn validate(alias: &str) -> Result<String, String> {
+    let alias = normalize(alias);
+
+    if alias.is_empty() || alias.chars().count() > MAX_ALIAS_LENGTH {
+        return Err(format!("Aliasul trebuie sa aiba intre 1 si {} caractere", MAX_ALIAS_LENGTH));
+    }
+    if !alias.chars().all(|x| { x.is_alphanumeric() || x == '_' || x == '-' }) {
+        return Err("In alias pot sa fie doar litere, cifre, '_' si '-'".to_string());
+    }
+    if i64::from_str(&alias).is_ok() || RESERVED.contains(&alias.as_str()) {
+        return Err(format!("Nu poti sa folosesti {} ca alias", alias));
+    }
+
+    Ok(alias)
+}
+
+impl Aliases {
+    pub fn new() -> Aliases {
+        Aliases::default()
+    }
+
+    // When the same alias shows up more times, the first one wins
+    pub fn from_pairs<I: IntoIterator<Item = (String, i64)>>(pairs: I) -> Aliases {
+        let mut aliases = Aliases::new();
+
+        for (alias, chat_id) in pairs {
+            if !aliases.insert(&alias, chat_id) {
+                log::warn!("Alias {} appears more than once, keeping the first one", alias);
+            }
+        }
+
+        aliases
+    }
+
+    pub fn get(&self, alias: &str) -> Option<i64> {
+        self.chats.get(&normalize(alias)).copied()
+    }
+
+    // The aliases of the chat, in alphabetical order
+    pub fn of_chat(&self, chat_id: i64) -> Vec<&String> {
+        let mut aliases: Vec<&String> = self.chats.iter()
+            .filter(|x| { *x.1 == chat_id })
+            .map(|x| { x.0 })
+            .collect();
+        aliases.sort();
+
+        aliases
+    }
+
+    // Returns false if the alias is already taken
+    pub fn insert(&mut self, alias: &str, chat_id: i64) -> bool {
+        let alias = normalize(alias);
+        if self.chats.contains_key(&alias) {
+            return false;
+        }
+
+        self.chats.insert(alias, chat_id);
+        true
+    }
+
+    // Returns the chat the alias belonged to
+    pub fn remove(&mut self, alias: &str) -> Option<i64> {
+        self.chats.remove(&normalize(alias))
+    }
+
+    // All the aliases as (alias, chat id), sorted by alias
+    pub fn pairs(&self) -> Vec<(String, i64)> {
+        let mut pairs: Vec<(String, i64)> = self.chats.iter()
+            .map(|x| { (x.0.clone(), *x.1) })
+            .collect();
+        pairs.sort();
+
+        pairs
+    }
+
+    pub fn count(&self) -> usize {
+        self.chats.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn more_aliases() {
+        let mut aliases = Aliases::new();
+        assert!(aliases.insert("Yeet", -5));
+        assert!(aliases.insert("aztecii", -5));
+        assert!(aliases.insert("altii", -6));
+        assert!(!aliases.insert(" YEET ", -6));
+
+        assert_eq!(aliases.get("yEEt"), Some(-5));
+        assert_eq!(aliases.of_chat(-5), vec!["aztecii", "yeet"]);
+        assert_eq!(aliases.remove("AZTECII"), Some(-5));
+        assert_eq!(aliases.of_chat(-5), vec!["yeet"]);
+        assert_eq!(aliases.remove("aztecii"), None);
+
+        assert_eq!(aliases.pairs(), vec![("altii".to_string(), -6), ("yeet".to_string(), -5)]);
+        assert_eq!(Aliases::from_pairs(vec![("Yeet".to_string(), -5), ("yeet".to_string(), -6)])
+                       .pairs(),
+                   vec![("yeet".to_string(), -5)]);
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(validate(" Aztecii_2-0 "), Ok("aztecii_2-0".to_string()));
+        assert_eq!(validate("fraţii"), Ok("fraţii".to_string()));
+        assert!(validate("").is_err());
+        assert!(validate("cu spatiu").is_err());
+        assert!(validate("a~b").is_err());
+        assert!(validate("-1001234").is_err());
+        assert!(validate("sterge").is_err());
+        assert!(validate(&"a".repeat(MAX_ALIAS_LENGTH + 1)).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+// Helpers for reading the settings of the bot from the environment
+
+use std::collections::HashSet;
+
+pub fn env_flag(name: &str, default: bool) -> bool {
+    match std::env::var(name) {
+    Ok(x) => {
+        match x.trim().to_lowercase().as_str() {
+        "1" | "true" | "da" => { true }
+        "0" | "false" | "nu" => { false }
+        _ => {
+            log::warn!("Invalid value for {}: {}, using {}", name, x, default);
+            default
+        }
+        }
+    }
+    Err(_) => { default }
+    }
+}
+
+pub fn env_number(name: &str, default: u64) -> u64 {
+    match std::env::var(name) {
+    Ok(x) => {
+        x.trim().parse().unwrap_or_else(|_| {
+            log::warn!("Invalid value for {}: {}, using {}", name, x, default);
+            default
+        })
+    }
+    Err(_) => { default }
+    }
+}
+
+pub fn env_signed(name: &str, default: i64) -> i64 {
+    match std::env::var(name) {
+    Ok(x) => {
+        x.trim().parse().unwrap_or_else(|_| {
+            log::warn!("Invalid value for {}: {}, using {}", name, x, default);
+            default
+        })
+    }
+    Err(_) => { default }
+    }
+}
+
+// A list of Telegram ids, separated by commas or spaces
+pub fn parse_ids(value: &str) -> Result<HashSet<i64>, String> {
+    value.split(|x: char| { x == ',' || x.is_whitespace() })
+        .filter(|x| { !x.is_empty() })
+        .map(|x| { x.parse().map_err(|_| { format!("Invalid id: {}", x) }) })
+        .collect()
+}
+
+// Command line flags that stand in for environment variables
+const FLAGS: &[(&str, &str)] = &[("--data-dir", "DATA_DIR"), ("--instance", "INSTANCE")];
+
+// The environment variable set by a flag and its value
+pub type Flag = (&'static str, String);
+
+// Takes the flags out of the arguments, as (environment variable, value) pairs. Both
+// "--flag value" and "--flag=value" work
+pub fn take_flags(args: Vec<String>) -> Result<(Vec<String>, Vec<Flag>), String> {
+    let mut rest = Vec::new();
+    let mut flags = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let (name, value) = match arg.split_once('=') {
+        Some((name, value)) => { (name.to_string(), Some(value.to_string())) }
+        None => { (arg.clone(), None) }
+        };
+
+        match FLAGS.iter().find(|x| { x.0 == name }) {
+        Some((_, variable)) => {
+            let value = match value.or_else(|| { args.next() }) {
+            Some(x) => { x }
+            None => { return Err(format!("Missing value for {}", name)); }
+            };
+
+            flags.push((*variable, value));
+        }
+        None => { rest.push(arg); }
+        }
+    }
+
+    Ok((rest, flags))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|x| { x.to_string() }).collect()
+    }
+
+    #[test]
+    fn flags() {
+        let (rest, flags) = take_flags(args(&["bot", "--data-dir", "/var/lib/trapperu",
+                                              "importa", "yeet", "--instance=doi", "chat.txt"]))
+            .unwrap();
+
+        assert_eq!(rest, args(&["bot", "importa", "yeet", "chat.txt"]));
+        assert_eq!(flags, vec![("DATA_DIR", "/var/lib/trapperu".to_string()),
+                               ("INSTANCE", "doi".to_string())]);
+
+        assert!(take_flags(args(&["bot", "--data-dir"])).is_err());
+    }
+
+    #[test]
+    fn ids() {
+        assert_eq!(parse_ids("1256262"), Ok(vec![1256262].into_iter().collect()));
+        assert_eq!(parse_ids(" 5, 6 7,,"), Ok(vec![5, 6, 7].into_iter().collect()));
+        assert_eq!(parse_ids(""), Ok(HashSet::new()));
+        assert!(parse_ids("5,ana").is_err());
+    }
+}
@@ -3,6 +3,8 @@ pub const BAD_PARANTHESES: &str = "Ai belit parantezele la expresie bombardiere"
 pub const BAD_CHARACTERS: &str = "Nush ce plm ai facut dar nu era corect";
 pub const BAD_OPERATOR: &str = "Wtf is this";
 
+pub const JOACO_LINK: &str = "https://www.youtube.com/watch?v=uMUaqROInGk";
+
 pub const HELP_DEFAULT: &str = "Cel mai adevarat bot, va arat cum se face smecherie.
 
 Lectii in smecherie, pe capitole (scrie randurile alea complet ca sa vezi capitolul). \
@@ -14,27 +16,48 @@ Cea mai importanta este clar alias, cititi-o pe aia mai intai ca aia e important
 /help taci
 /help help
 /help gind
+/help dao
+/help comenzi
+/help setari
+/help ceva
+/help rol
+/help admin
 
 Celalalte comenzi de pe acolo care mai apar momentan sunt la harneala, mai aveti rabdare
 ";
 
 pub const HELP_ALIAS_TAB: &str = "alias";
-pub const HELP_ALIAS: &str = "Tin minte o porecla acolo pt grup ca sa ne fie la toti mai usor
+pub const HELP_ALIAS: &str = "Tin minte niste porecle acolo pt grup ca sa ne fie la toti mai usor
 
 /alias
-Aici iti zic id-ul grupului si aliasul grupului.
+Aici iti zic id-ul grupului si aliasurile grupului.
 
 /alias [nume]
-Setez porecla grupului in [nume]. Vezi ca asta nu apare nicaieri pe telegram sau ceva, \
+Ii mai dau grupului o porecla, [nume]. Vezi ca asta nu apare nicaieri pe telegram sau ceva, \
 doar il tin eu minte sa stiu cum dreq sa va strig. Toate celalalte comenzi se vor folosi \
-de aliasul asta
+de aliasurile astea, oricare din ele, si nu conteaza literele mari. In alias pot sa fie \
+doar litere, cifre, '_' si '-'. Doar moderatorii grupului pot sa puna aliasuri (vezi \
+/help rol), si tin minte cine le-a pus.
+
+/alias sterge [nume]
+Sterg aliasul [nume] al grupului, ca sa-l poata lua altcineva. Tot doar pentru moderatori.
+
+Cu aliasul poti sa bagi comenzi si ginduri pe grup si din alta parte, dar doar daca esti \
+si tu pe grupul ala.
 ";
 
 pub const HELP_HELP_TAB: &str = "help";
 pub const HELP_HELP: &str = "Te ajut in pula mea calmeaza-te";
 
 pub const HELP_TACI_TAB: &str = "taci";
-pub const HELP_TACI: &str = "Incearca sa vezi ce face /taci, hai coaie te provoc, nu te tine";
+pub const HELP_TACI: &str = "Incearca sa vezi ce face /taci, hai coaie te provoc, nu te tine
+
+/taci [cit]
+Nu mai raspund la comenzile bagate cu /adauga pe grupul asta pentru [cit] (gen 30m, 2h, 1d, \
+cel mult 30 de zile). Restul comenzilor merg, si tot invat pentru /dao. Doar pentru moderatori.
+
+/taci gata
+Raspund iar la comenzi.";
 
 pub const HELP_JOACO_TAB: &str = "joaco";
 pub const HELP_JOACO: &str = "[LYRICS]
@@ -77,22 +100,204 @@ zice cuvantul a si unul din cuvintele b si c absolut oriunde in propozitie.
 [Alias] este porecla grupului, daca vrei sa bagi o comanda si sa nu vada ceilalti, intri \
 la mine in DM si folosesti varianta a doua de mai sus cu aliasul ala (da vezi ca trebuie \
 sa setezi aliasul ala cand ma bagi pe grup). Daca ai uitat aliasul sau nu stii cum, \
-doar dai '/alias' si gata te-ai scos.";
+doar dai '/alias' si gata te-ai scos.
+
+Daca nu vrei sa te chinui cu '~', da-mi doar /adauga in DM. Te intreb pe rand grupul (cu \
+butoane sau scrii aliasul), expresia, mesajul si la sfarsit daca o adaug. Daca ai gresit \
+expresia iti zic ce si o scrii iar. Cu /anuleaza renunti oricand.";
 
 pub const WRONG_ALIAS: &str = "Ceai facut bombardiere, ai gresit aliasul?";
 
+pub const WIZARD_GROUP: &str = "Pe ce grup bagam comanda? Apasa pe unul sau scrie-mi aliasul";
+pub const WIZARD_EXPRESSION: &str = "Zi expresia, de genul a&(b|c)";
+pub const WIZARD_RESPONSE: &str = "Si ce raspund cand e adevarata?";
+pub const WIZARD_PRESS_BUTTON: &str = "Apasa pe Adauga sau pe Anuleaza, sau da /anuleaza";
+pub const WIZARD_ONLY_TEXT: &str = "Scrie-mi text, nu altceva";
+pub const WIZARD_CANCELLED: &str = "Bine, lasam";
+pub const WIZARD_OLD_BUTTON: &str = "Butonul asta nu mai merge";
+pub const WIZARD_NOTHING: &str = "N-aveam nimic de anulat";
+
 pub const HELP_GIND_TAB: &str = "gind";
 pub const HELP_GIND: &str = "Aici ai doua comenzi importante, una din ele avand si: \
 ea doua variante.
 
 /gindeste [Mesaj]
 /gindeste [Alias]~[Mesaj]
+/gindeste [Alias]~[Cind]~[Mesaj]
 /gind
+/gind count
+/gind mod [random|fifo|lifo]
+/gind sterge [ce]
+/gind sterge [Alias]~[ce]
 
 Daca dai prima varianta, o sa ascult un gind pe care sa-l tin minte in viitor in grupul \
 de pe care ai dat comanda.
 Daca dai a doua varianta, o sa fac acelasi lucru ca prima, numai ca pe grupul cu aliasul \
 dat, gen ca si la /adauga.
+Daca pui si [Cind], nu mai astept sa-mi ceara cineva gindul, il zic singur pe grup atunci. \
+[Cind] poate fi \"in 2h\", \"peste 1h 30m\", \"in 3d\" sau o data ca \"2021-05-01 18:00\". \
+Aliasul il poti sari si aici.
+Pe alt grup poti sa bagi ginduri doar daca esti si tu pe el. Gindurile goale nu le iau, \
+nici pe cele mai lungi de 1000 de caractere.
 Daca dai /gind, iti dau un gind frumos pe care l-am tinut minte si il zic pe grup, dar \
-vezi ca nu o sa zic acelasi gind de doua ori ca nu sunt fraier";
+vezi ca nu o sa zic acelasi gind de doua ori ca nu sunt fraier. Sub gind zic si de cand \
+il tin minte, dar nu si cine l-a zis.
+Cu /gind count iti zic cate ginduri mai am, fara sa zic vreunul.
+Cu /gind mod alegi ce gind zic: unul la intimplare (random, asa e la inceput), cel mai vechi \
+(fifo) sau cel mai nou (lifo). Doar moderatorii pot sa-l schimbe.
+/gind sterge e doar pentru admin: arunc fara sa le zic gindurile care contin [ce], sau \
+toate gindurile lui [ce] daca [ce] e @user. Merge si pe grupul cu aliasul dat.";
+
+pub const GIND_EMPTY: &str = "Nu gindesc, deci nu exist";
+pub const GIND_NO_TEXT: &str = "Si la ce sa ma gindesc, la nimic?";
+pub const UNKNOWN_CHAT: &str = "Nu stiu chatul asta, nu sunt pe el";
+pub const BANNED: &str = "Esti banat aici, nu mai faci nimic";
+pub const NOT_A_MEMBER: &str = "Nu esti pe grupul ala, ce te bagi?";
+
+
+pub const DAO_EMPTY: &str = "n-o dau da-te dreq";
+pub const DAO_UNKNOWN_USER: &str = "Nu-l stiu pe asta, n-a zis nimic pe aici";
+pub const DAO_NO_IMITATION: &str = "Asta nu vrea sa fie imitat, respect";
+pub const IMITATION_OFF: &str = "Bine ma, nu te mai imit. Daca vrei sa uit si ce ai zis, da /uita_ma";
+pub const TRAINING_OFF: &str = "Bine, nu mai invat nimic din ce zici. Ce am invatat deja uit cu /uita_ma";
+pub const TRAINING_ON: &str = "Gata, invat iar din ce zici";
+pub const IMITATION_ON: &str = "Gata, de acum incolo invat cum vorbesti";
+
+pub const HELP_DAO_TAB: &str = "dao";
+pub const HELP_DAO: &str = "Invat cum vorbiti si dau si eu cate o replica.
+
+/dao
+Zic o propozitie in stilul grupului.
+
+/dao @[user]
+Zic o propozitie in stilul lui [user], daca a zis ceva pe aici de cand il tin minte.
+
+/dao stats
+Iti zic cat am invatat pe grupul asta si ce cuvinte folositi cel mai des.
+
+/dao next [cuvant]
+Iti zic ce cuvinte vin dupa [cuvant] si cu ce sanse.
+
+/nu_ma_imita
+Nu te mai imit cu /dao @[user] pe grupul asta. Tot ce zici intra in continuare la /dao \
+simplu, si tin minte in continuare ce am invatat de la tine, ca sa pot sa scot cu /uita_ma. \
+Daca nu vrei sa tin nimic de la tine, da /nu_ma_invata si /uita_ma.
+
+/imita_ma
+Te imit iar.
+
+/nu_ma_invata
+Nu mai invat nimic din ce zici pe grupul asta, nici pentru /dao simplu.
+
+/invata_ma
+Invat iar din ce zici.
+
+/uita_ma
+Uit tot ce am invatat de la tine pe grupul asta, si de la /dao simplu. Moderatorii pot sa \
+dea asta si cu reply la mesajul altuia, sau cu /uita_ma @[user], ca sa-l uit pe el. \
+Ce am invatat inainte sa tin minte cine ce zice nu mai pot sa scot.
+
+Tot grupul poate sa nu mai fie invatat cu /setari (invata nu).
+
+/uita [cuvant]
+/uita [Alias]~[cuvant]
+Doar pentru admin. Uit de tot cuvantul [cuvant] (nu conteaza literele mari sau semnele), \
+de pe grupul asta sau de pe grupul cu aliasul dat.";
+
+pub const HELP_COMENZI_TAB: &str = "comenzi";
+pub const HELP_COMENZI: &str = "Comenzile bagate cu /adauga pe grupul asta
+
+/comenzi
+Iti zic toate comenzile, numerotate, ca [expresie] -> [raspuns].
+
+/comenzi sterge [numar]
+Sterg comanda cu numarul [numar] din /comenzi. Doar pentru moderatori. Vezi ca dupa asta \
+se schimba numerele comenzilor de dupa ea.";
+
+pub const HELP_SETARI_TAB: &str = "setari";
+pub const HELP_SETARI: &str = "Ce pot sa schimbati la mine pe grupul asta
+
+/setari
+Iti arat setarile grupului, cu un buton pentru fiecare. Apesi pe buton si trec setarea la \
+urmatoarea valoare.
+
+/setari [setare] [valoare]
+Schimb setarea de mana. Setarile sunt:
+invata (da/nu) - daca invat din mesaje pentru /dao
+comenzi (da/nu) - daca raspund la comenzile bagate cu /adauga
+raspuns (mesaj/reply) - daca raspund la comenzi cu un mesaj nou sau cu reply la mesaj
+
+Doar moderatorii pot sa schimbe setarile.";
+
+pub const HELP_CEVA_TAB: &str = "ceva";
+pub const HELP_CEVA: &str = "Cind nu stii ce vrei de la mine
+
+/ceva
+Fac ceva la intimplare din ce stiu pe grupul asta: zic o propozitie ca la /dao, un gind \
+(fara sa-l scot, ramane pentru /gind), raspunsul unei comenzi sau dau joaco.
+
+/ceva ponderi
+Iti zic cat de des fac fiecare lucru.
+
+/ceva [dao|gind|comanda|joaco] [pondere]
+Schimb cat de des fac lucrul ala, cu o pondere de la 0 la 100. Cu 0 nu-l mai fac deloc. \
+La inceput toate au ponderea 1. Doar pentru moderatori.";
+
+pub const HELP_ROL_TAB: &str = "rol";
+pub const HELP_ROL: &str = "Pe fiecare grup, fiecare are un rol: banat, membru, moderator sau sef. \
+Adminii grupului sunt macar moderatori si cel care a facut grupul e sef.
+
+Membrii pot sa bage comenzi, ginduri si sa foloseasca /dao. Banatii nu pot nici atat. \
+Moderatorii mai pot sa stearga comenzi, sa schimbe setarile grupului (/setari, aliasuri, \
+/gind mod), sa ma faca sa tac cu /taci [cit] si sa dea roluri. Sefii pot sa faca orice.
+
+/rol
+Iti zic ce rol ai tu si cine are alt rol decat membru.
+
+/rol [@user sau id]
+Iti zic ce rol are [user].
+
+/rol [@user sau id] [rol]
+Ii dau lui [user] rolul [rol]. Merge si cu reply la un mesaj de-al lui, cu /rol [rol]. \
+Moderatorii pot sa baneze si sa debaneze membri, restul doar sefii.";
+
+// Telegram doesn't send longer messages than 4096 characters
+pub const MAX_MESSAGE_LENGTH: usize = 4000;
+
+pub const ONLY_ADMIN: &str = "Doar sefu poate sa faca asta";
+pub const SENT_IN_PRIVATE: &str = "Ti-am trimis-o in privat";
+pub const NO_PRIVATE_CHAT: &str = "Nu pot sa-ti scriu in privat, da-mi tu un mesaj intai";
+pub const IMPORT_NO_DOCUMENT: &str = "Da reply cu /import la fisierul pe care vrei sa-l bag";
+pub const IMPORT_TOO_BIG: &str = "Fisierul e prea mare bombardiere";
+
+pub const HELP_ADMIN_TAB: &str = "admin";
+pub const HELP_ADMIN: &str = "Comenzi doar pentru admin.
+
+/backup
+Iti trimit in privat tot ce stiu, in data2.JSON si aliases.JSON. Le pui in directorul \
+cu date (cu botul oprit) si o iau de unde am ramas.
+
+/export
+/export [Alias]
+Iti trimit in privat grupul asta sau grupul cu aliasul dat: comenzile, gindurile si ce \
+am invatat pentru /dao. Ce stiu despre fiecare om ramane la mine.
+
+/import
+/import [Alias]
+Dai reply cu asta la un fisier facut cu /export si il bag in grupul asta sau in grupul \
+cu aliasul dat. Comenzile si gindurile pe care le am deja nu le bag de doua ori.
+
+/stats
+Iti zic cat stiu pe toate grupurile la un loc.
+
+/groups
+Iti zic pe ce grupuri sunt, cu aliasurile lor si cate comenzi au.
+
+/broadcast [mesaj]
+Zic [mesaj] pe toate grupurile pe care le stiu.
+
+/reload
+Uit tot ce am in memorie si citesc iar de pe disc. Ce nu era salvat se pierde.
 
+/save
+Salvez tot acum, fara sa ma opresc.";
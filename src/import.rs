@@ -0,0 +1,165 @@
+use std::fs;
+use serde_json::Value;
+
+// A message read from a chat history, before going through the training filter
+#[derive(Debug, PartialEq, Clone)]
+pub struct ImportedMessage {
+    pub user_id: Option<i64>,
+    pub text: String,
+    pub forwarded: bool,
+    pub from_bot: bool,
+}
+
+// Telegram Desktop writes the text either as a string or as a list of strings and
+// formatted entities like {"type": "bold", "text": "..."}
+fn export_text(text: &Value) -> String {
+    match text {
+    Value::String(x) => { x.clone() }
+    Value::Array(parts) => {
+        parts.iter()
+            .map(|part| {
+                match part {
+                Value::String(x) => { x.as_str() }
+                _ => { part.get("text").and_then(|x| { x.as_str() }).unwrap_or("") }
+                }
+            })
+            .collect()
+    }
+    _ => { String::new() }
+    }
+}
+
+// The sender looks like "user123456", channels and other stuff don't have a user id
+fn export_user_id(message: &Value) -> Option<i64> {
+    message.get("from_id")
+        .and_then(|x| { x.as_str() })
+        .and_then(|x| { x.strip_prefix("user") })
+        .and_then(|x| { x.parse().ok() })
+}
+
+// Parses the result.json of a chat exported with Telegram Desktop
+pub fn parse_telegram_export(content: &str) -> Result<Vec<ImportedMessage>, String> {
+    let export: Value = serde_json::from_str(content)
+        .map_err(|x| { format!("Failed to parse the export: {}", x) })?;
+
+    let messages = export.get("messages")
+        .and_then(|x| { x.as_array() })
+        .ok_or_else(|| { "The export doesn't have a list of messages".to_string() })?;
+
+    Ok(messages.iter()
+        .filter(|x| { x.get("type").and_then(|x| { x.as_str() }) == Some("message") })
+        .map(|x| {
+            ImportedMessage {
+                user_id: export_user_id(x),
+                text: x.get("text").map(export_text).unwrap_or_default(),
+                forwarded: x.get("forwarded_from").is_some(),
+                from_bot: x.get("via_bot").is_some(),
+            }
+        })
+        .filter(|x| { !x.text.trim().is_empty() })
+        .collect())
+}
+
+// Every non-empty line is a message from nobody in particular
+pub fn parse_plain_text(content: &str) -> Vec<ImportedMessage> {
+    content.lines()
+        .filter(|x| { !x.trim().is_empty() })
+        .map(|x| {
+            ImportedMessage {
+                user_id: None,
+                text: x.to_string(),
+                forwarded: false,
+                from_bot: false,
+            }
+        })
+        .collect()
+}
+
+pub fn read_history(path: &str) -> Result<Vec<ImportedMessage>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|x| { format!("Failed to read {}: {}", path, x) })?;
+
+    if path.to_lowercase().ends_with(".json") || content.trim_start().starts_with('{') {
+        parse_telegram_export(&content)
+    } else {
+        Ok(parse_plain_text(&content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn telegram_export() {
+        let export = r#"{
+            "name": "Aztecii",
+            "type": "private_supergroup",
+            "id": 123,
+            "messages": [
+                {"id": 1, "type": "service", "action": "create_group", "actor_id": "user1"},
+                {"id": 2, "type": "message", "from": "Ian", "from_id": "user42",
+                 "text": "Iane, joaco"},
+                {"id": 3, "type": "message", "from": "Oscar", "from_id": "user43",
+                 "text": ["uite ", {"type": "link", "text": "https://youtu.be/x"}, " aici"]},
+                {"id": 4, "type": "message", "from": "Ian", "from_id": "user42",
+                 "forwarded_from": "Altcineva", "text": "spam"},
+                {"id": 5, "type": "message", "from": "Canal", "from_id": "channel7",
+                 "via_bot": "@gif", "text": "gif"},
+                {"id": 6, "type": "message", "from": "Ian", "from_id": "user42",
+                 "photo": "photos/1.jpg", "text": ""}
+            ]
+        }"#;
+
+        assert_eq!(parse_telegram_export(export), Ok(vec![
+            ImportedMessage {
+                user_id: Some(42),
+                text: "Iane, joaco".to_string(),
+                forwarded: false,
+                from_bot: false,
+            },
+            ImportedMessage {
+                user_id: Some(43),
+                text: "uite https://youtu.be/x aici".to_string(),
+                forwarded: false,
+                from_bot: false,
+            },
+            ImportedMessage {
+                user_id: Some(42),
+                text: "spam".to_string(),
+                forwarded: true,
+                from_bot: false,
+            },
+            ImportedMessage {
+                user_id: None,
+                text: "gif".to_string(),
+                forwarded: false,
+                from_bot: true,
+            },
+        ]));
+    }
+
+    #[test]
+    fn bad_export() {
+        assert!(parse_telegram_export("{\"name\": \"Aztecii\"}").is_err());
+        assert!(parse_telegram_export("nu e json").is_err());
+    }
+
+    #[test]
+    fn plain_text() {
+        assert_eq!(parse_plain_text("Sa moara familia mea\n\n  \nlasama\n"), vec![
+            ImportedMessage {
+                user_id: None,
+                text: "Sa moara familia mea".to_string(),
+                forwarded: false,
+                from_bot: false,
+            },
+            ImportedMessage {
+                user_id: None,
+                text: "lasama".to_string(),
+                forwarded: false,
+                from_bot: false,
+            },
+        ]);
+    }
+}
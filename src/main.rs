@@ -1,29 +1,45 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
 use std::str::FromStr;
-use std::io::prelude::*;
-use std::fs::File;
-use std::fs;
-use std::borrow::Borrow;
+use std::path::Path;
+use std::time::Duration;
 
 use lazy_static::lazy_static;
 
 use teloxide::prelude::*;
+use teloxide::dispatching::{update_listeners, DispatcherHandler};
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use teloxide::utils::command::BotCommand;
-use teloxide::types::{MessageKind, MediaKind};
+use teloxide::net::Download;
+use teloxide::types::{ChatMemberStatus, InlineKeyboardButton, InlineKeyboardMarkup, InputFile,
+                      MessageKind, MediaKind};
 
 use crate::trapper::adauga::Expression;
 use crate::trapper::Trapper;
-use crate::trapper::dao::Markov;
-use bimap::BiMap;
+use crate::trapper::dao::{ChainLimits, Markov, TrainingFilter};
+use crate::trapper::gind::{parse_gindeste, Thought, ThoughtOrder};
+use crate::trapper::ceva::{CevaAction, CEVA_ACTIONS};
+use crate::trapper::rol::{Action, Role};
+use crate::trapper::setari::{GroupSettings, ReplyMode, Setting, SETTINGS};
 use crate::constants::*;
+use crate::aliases::Aliases;
+use crate::config::{env_number, env_signed};
+use crate::storage::Storage;
+use crate::storage::sqlite::SqliteStorage;
+use crate::wizard::ChatUpdate;
 
 mod trapper;
+mod aliases;
 mod constants;
+mod config;
+mod import;
+mod storage;
+mod wizard;
 
 lazy_static! {
-    static ref ALIASES: Arc<Mutex<BiMap<String, i64>>> = {
+    static ref ALIASES: Arc<Mutex<Aliases>> = {
         Arc::new(Mutex::new(load_aliases()))
     };
     static ref STATEMAP: Arc<Mutex<HashMap<i64, trapper::Trapper> > > = {
@@ -33,102 +49,318 @@ lazy_static! {
         std::env::var("BOT_NAME")
             .expect("BOT_NAME environment variable not set")
     };
-    static ref ADMIN_ID: String = {
-        std::env::var("ADMIN_ID")
-            .expect("ADMIN_ID environment variable not set")
+    // ADMIN_ID may hold more ids, separated by commas
+    static ref ADMIN_IDS: HashSet<i64> = {
+        let ids = std::env::var("ADMIN_ID").expect("ADMIN_ID environment variable not set");
+        config::parse_ids(&ids).expect("Invalid ADMIN_ID")
     };
+    static ref TRAINING_FILTER: TrainingFilter = {
+        TrainingFilter::from_env()
+    };
+    static ref CHAIN_LIMITS: ChainLimits = {
+        ChainLimits::from_env()
+    };
+    // Only one save may write at a time
+    static ref STORAGE: Mutex<Box<dyn Storage>> = {
+        Mutex::new(storage::from_env().expect("Failed to open the storage"))
+    };
+    // Chats changed since the last save
+    static ref CHANGED_CHATS: Mutex<HashSet<i64>> = Mutex::new(HashSet::new());
+    static ref AUTOSAVE: tokio::sync::Notify = tokio::sync::Notify::new();
+    static ref AUTOSAVE_SECONDS: u64 = env_number("AUTOSAVE_SECONDS", 300);
+    static ref AUTOSAVE_MUTATIONS: usize = env_number("AUTOSAVE_MUTATIONS", 100) as usize;
+    // How long a thought waits for /gind before it's dropped, 0 meaning forever
+    static ref THOUGHT_LIFETIME: u64 = env_number("GIND_EXPIRY_DAYS", 0) * 24 * 60 * 60;
+    // The time zone of the dates given to /gindeste, in minutes ahead of UTC
+    static ref UTC_OFFSET: i64 = env_signed("UTC_OFFSET_MINUTES", 0);
+    static ref MAX_IMPORT_BYTES: u64 = env_number("MAX_IMPORT_KB", 10 * 1024) * 1024;
+    // Becomes true when the bot should stop
+    static ref SHUTDOWN: (watch::Sender<bool>, watch::Receiver<bool>) = watch::channel(false);
+    static ref UPDATES_DONE: tokio::sync::Notify = tokio::sync::Notify::new();
 }
 
+// The data of the /setari buttons starts with this, the name of the setting follows
+const SETTINGS_CALLBACK: &str = "setari:";
 
-fn save_bot_data() {
-    log::info!("Saving all bot data");
+// The longest a group can mute the triggers with /taci
+const MAX_MUTE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
 
-    let mut statemap = STATEMAP.lock().unwrap();
-    
-    log::info!("Serializing statemap");
-    let serialized_statemap = serde_json::to_string(&*statemap).unwrap();
-    let file = File::create("data2.JSON");
-    match file {
-    Ok(mut file) => {
-        file.write_all(serialized_statemap.as_bytes())
-            .expect("Failed to write all the data in the file");
+// Changes to the state since the last save
+static MUTATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALIASES_CHANGED: AtomicBool = AtomicBool::new(false);
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+// The id of the last update given to the handlers
+static LAST_UPDATE: AtomicI32 = AtomicI32::new(-1);
+// Updates given to the wizard and not handled yet. Its tasks never end, so the shutdown
+// waits for this to get to 0 instead
+static UPDATES_IN_PROGRESS: AtomicUsize = AtomicUsize::new(0);
+
+fn update_started() {
+    UPDATES_IN_PROGRESS.fetch_add(1, Ordering::SeqCst);
+}
+
+// Marks the update as handled when dropped, so it counts even if handling it panicked
+struct UpdateDone;
+
+impl Drop for UpdateDone {
+    fn drop(&mut self) {
+        update_done();
     }
-    Err(x) => {
-        log::info!("Failed to create data2.JSON: {}", x);
+}
+
+fn update_done() {
+    if UPDATES_IN_PROGRESS.fetch_sub(1, Ordering::SeqCst) == 1 {
+        UPDATES_DONE.notify_one();
+    }
+}
+
+async fn wait_updates() {
+    while UPDATES_IN_PROGRESS.load(Ordering::SeqCst) != 0 {
+        UPDATES_DONE.notified().await;
     }
+}
+
+fn count_mutation() {
+    let mutations = MUTATIONS.fetch_add(1, Ordering::SeqCst) + 1;
+
+    if *AUTOSAVE_MUTATIONS != 0 && mutations >= *AUTOSAVE_MUTATIONS {
+        AUTOSAVE.notify_one();
     }
+}
 
-    log::info!("Saved all bot data");
+// Call this after every change to the Trapper of a chat, so the autosave knows about it
+fn mark_dirty(chat_id: i64) {
+    CHANGED_CHATS.lock().unwrap().insert(chat_id);
+    count_mutation();
 }
 
-fn save_aliases() {
-    log::info!("Saving all aliases");
+// Same as mark_dirty, for ALIASES
+fn mark_aliases_dirty() {
+    ALIASES_CHANGED.store(true, Ordering::SeqCst);
+    count_mutation();
+}
 
-    let aliases = ALIASES.lock().unwrap();
-    let all_aliases: Vec<(String, i64)> = aliases.iter().map(|x| { (x.0.clone(), *x.1) } )
-        .collect();
+// The groups are copied out of STATEMAP and written after it's unlocked, so the handlers
+// don't wait for the disk. Call this from a blocking thread
+fn save_bot_data(storage: &mut dyn Storage, changed: &HashSet<i64>) -> Result<(), String> {
+    log::info!("Saving all bot data");
+
+    let snapshot: HashMap<i64, Trapper> = {
+        let statemap = STATEMAP.lock().unwrap();
+        if storage.incremental() {
+            changed.iter()
+                .filter_map(|chat_id| { statemap.get(chat_id).map(|x| { (*chat_id, x.clone()) }) })
+                .collect()
+        } else {
+            statemap.clone()
+        }
+    };
+
+    match storage.save_trappers(&snapshot, changed) {
+    Ok(()) => {
+        log::info!("Saved all bot data");
+        Ok(())
+    }
+    Err(x) => {
+        log::error!("Failed to save the bot data: {}", x);
+        Err(x)
+    }
+    }
+}
 
-    let serialized = serde_json::to_string(&all_aliases).unwrap();
+fn save_aliases(storage: &mut dyn Storage) -> Result<(), String> {
+    log::info!("Saving all aliases");
 
-    let file = File::create("aliases.JSON");
-    match file {
-    Ok(mut file) => {
-        file.write_all(serialized.as_bytes())
-            .expect("Failed to write all the data in the file");
+    let aliases = ALIASES.lock().unwrap().clone();
+    match storage.save_aliases(&aliases) {
+    Ok(()) => {
         log::info!("Saved all aliases");
+        Ok(())
     }
     Err(x) => {
-        log::info!("Failed to create aliases.JSON: {}", x);
+        log::error!("Failed to save the aliases: {}", x);
+        Err(x)
+    }
+    }
+}
+
+// What failed to save is marked as changed again, so the next save tries it again
+fn save_all() -> Result<(), String> {
+    let mut storage = STORAGE.lock().unwrap();
+
+    MUTATIONS.store(0, Ordering::SeqCst);
+    let changed = std::mem::take(&mut *CHANGED_CHATS.lock().unwrap());
+    let saved_data = save_bot_data(storage.as_mut(), &changed);
+    if saved_data.is_err() {
+        CHANGED_CHATS.lock().unwrap().extend(changed);
+    }
+
+    let saved_aliases = if ALIASES_CHANGED.swap(false, Ordering::SeqCst) {
+        let saved = save_aliases(storage.as_mut());
+        if saved.is_err() {
+            ALIASES_CHANGED.store(true, Ordering::SeqCst);
+        }
+        saved
+    } else {
+        Ok(())
+    };
+
+    saved_data.and(saved_aliases)
+}
+
+// Throws away everything in memory for what's in the storage, changes since the last save
+// included
+fn reload_all() -> Result<(usize, usize), String> {
+    let mut storage = STORAGE.lock().unwrap();
+    let new_aliases = storage.load_aliases()?;
+    let new_statemap = storage.load_trappers()?;
+    let counts = (new_statemap.len(), new_aliases.count());
+
+    let mut aliases = ALIASES.lock().unwrap();
+    let mut statemap = STATEMAP.lock().unwrap();
+    *aliases = new_aliases;
+    *statemap = new_statemap;
+
+    CHANGED_CHATS.lock().unwrap().clear();
+    ALIASES_CHANGED.store(false, Ordering::SeqCst);
+    MUTATIONS.store(0, Ordering::SeqCst);
+    log::info!("Reloaded {} groups and {} aliases", counts.0, counts.1);
+
+    Ok(counts)
+}
+
+// Saves everything every AUTOSAVE_SECONDS seconds or after AUTOSAVE_MUTATIONS changes,
+// whatever comes first, so a crash doesn't throw away everything since the start
+async fn autosave() {
+    let mut interval = tokio::time::interval(Duration::from_secs((*AUTOSAVE_SECONDS).max(1)));
+    interval.tick().await;
+
+    loop {
+        if *AUTOSAVE_SECONDS == 0 {
+            AUTOSAVE.notified().await;
+        } else {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = AUTOSAVE.notified() => {}
+            }
+        }
+
+        if MUTATIONS.load(Ordering::SeqCst) == 0 {
+            continue;
+        }
+
+        log::info!("Autosaving");
+        match tokio::task::spawn_blocking(save_all).await {
+        Ok(_) => {}
+        Err(x) => { log::error!("Autosave failed: {}", x); }
+        }
     }
+}
+
+// Posts the scheduled thoughts when they're due, until the bot stops
+async fn deliver_thoughts(bot: AutoSend<Bot>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(30));
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown_requested() => { return; }
+        }
+        let now = trapper::now();
+
+        let due: Vec<(i64, Thought)> = {
+            let mut statemap = STATEMAP.lock().unwrap();
+            let mut due = vec![];
+
+            for (chat_id, trapper) in statemap.iter_mut() {
+                let thoughts = trapper.take_due_thoughts(now);
+                if !thoughts.is_empty() {
+                    mark_dirty(*chat_id);
+                }
+                due.extend(thoughts.into_iter().map(|x| { (*chat_id, x) }));
+            }
+
+            due
+        };
+
+        // If we can't post on the group anymore, the thought is lost, there's nobody to
+        // tell it to anyway
+        for (chat_id, thought) in due {
+            if let Err(x) = bot.send_message(chat_id, thought.reveal(now)).await {
+                log::warn!("Failed to post a scheduled thought on {}: {}", chat_id, x);
+            }
+        }
     }
 }
 
-fn exit_program() {
-    save_bot_data();
-    save_aliases();
+// Stops taking updates. The ones already taken are still handled, then everything is
+// saved once and the bot exits. Returns false if the bot was already stopping
+fn request_shutdown() -> bool {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        return false;
+    }
 
     log::info!("Shutting down bot...");
-    std::process::exit(0);
+    SHUTDOWN.0.send(true).ok();
+    true
+}
+
+async fn shutdown_requested() {
+    let mut shutdown = SHUTDOWN.1.clone();
+    while !*shutdown.borrow() {
+        if shutdown.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+// Ctrl-C and SIGTERM. A second one doesn't wait for anything anymore
+fn on_signal() {
+    if !request_shutdown() {
+        log::warn!("Stopping right away, without saving");
+        std::process::exit(1);
+    }
 }
 
 fn load_bot_data() -> HashMap<i64, trapper::Trapper> {
     log::info!("Loading all commands");
-    let deserialized = fs::read_to_string("data2.JSON");
-    let deserialized = match deserialized {
-    Ok(x) => { x }
-    Err(x) => {
-        log::error!("Failed to read data from data2.JSON: {}", x);
-        String::new()
-    }
-    };
 
-    let statemap = serde_json::from_str(&deserialized);
-    
-    match statemap {
-    Ok(x) => { x }
+    match STORAGE.lock().unwrap().load_trappers() {
+    Ok(x) => {
+        log::info!("Loaded {} groups", x.len());
+        x
+    }
     Err(x) => {
-        log::error!("Failed deserializing data2.JSON: {}", x);
-        panic!();
+        log::error!("{}", x);
+        panic!("Can't load the bot data, fix the error above and try again");
     }
     }
 }
 
-fn load_aliases() -> BiMap<String, i64> {
+fn load_aliases() -> Aliases {
     log::info!("Loading all aliases");
-    
-    let deserialized = fs::read_to_string("aliases.JSON").unwrap();
-    let mut all_aliases: Vec<(String, i64)> = serde_json::from_str(&deserialized).unwrap();
-    let mut aliases: BiMap<String, i64> = BiMap::new();
 
-    while !all_aliases.is_empty() {
-        // this shouldn't panic
-        let (alias, id) = all_aliases.pop().unwrap();
-        aliases.insert(alias, id);
+    match STORAGE.lock().unwrap().load_aliases() {
+    Ok(x) => {
+        log::info!("Loaded {} aliases", x.count());
+        x
+    }
+    Err(x) => {
+        log::error!("{}", x);
+        panic!("Can't load the aliases, fix the error above and try again");
     }
+    }
+}
 
-    log::info!("Loaded all aliases");
+// Copies everything from the JSON files into the SQLite database
+fn migrate_to_sqlite(path: &Path) -> Result<(), String> {
+    let mut json = storage::json_storage()?;
+    let mut sqlite = SqliteStorage::open(path)?;
 
-    aliases
+    let (aliases, groups) = storage::copy_storage(&mut json, &mut sqlite)?;
+    log::info!("Copied {} aliases and {} groups", aliases, groups);
+
+    Ok(())
 }
 
 #[derive(BotCommand, Debug)]
@@ -137,221 +369,1328 @@ enum BotCommands {
     #[command(description = "Iane, joaco")]
     Joaco,
     #[command(description = "Taci in pula mea de bot handicapat")]
-    Taci,
+    Taci(String),
     #[command(description = "Adauga o comanda blana")]
     Adauga(String),
     #[command(description = "cu ce te ajut sacale?")]
     Help(String),
     #[command(description = "Cum ma cunoaste lumea in cartier")]
     Alias(String),
+    #[command(description = "Ce comenzi stiu pe grupul asta")]
+    Comenzi(String),
+    #[command(description = "Cine ce poate sa faca pe grup")]
+    Rol(String),
+    #[command(description = "Ce pot sa schimbati la mine pe grup")]
+    Setari(String),
     #[command(description = "Ati spun un gind frumos de la altii")]
-    Gind,
+    Gind(String),
     #[command(description = "Gandesc")]
     Gindeste(String),
 
     #[command(description = "Noi fumam cioate in timp ce o dam")]
-    Dao,
+    Dao(String),
+    #[command(rename = "nu_ma_imita", description = "Nu ma mai imita cu /dao")]
+    NuMaImita,
+    #[command(rename = "imita_ma", description = "Poti sa ma imiti cu /dao")]
+    ImitaMa,
+    #[command(rename = "nu_ma_invata", description = "Nu mai invata din ce zic")]
+    NuMaInvata,
+    #[command(rename = "invata_ma", description = "Poti sa inveti iar din ce zic")]
+    InvataMa,
+    #[command(rename = "uita_ma", description = "Uita tot ce ai invatat de la mine")]
+    UitaMa(String),
+    #[command(description = "Uit un cuvant din ce am invatat pentru /dao")]
+    Uita(String),
     #[command(description = "idk, fa ceva")]
     Ceva(String),
+    #[command(description = "Iti trimit tot ce stiu in privat (doar pentru admin)")]
+    Backup,
+    #[command(description = "Iti trimit un grup in privat (doar pentru admin)")]
+    Export(String),
+    #[command(description = "Bag un grup exportat (doar pentru admin)")]
+    Import(String),
+    #[command(description = "Cat stiu pe toate grupurile (doar pentru admin)")]
+    Stats,
+    #[command(description = "Pe ce grupuri sunt (doar pentru admin)")]
+    Groups,
+    #[command(description = "Zic ceva pe toate grupurile (doar pentru admin)")]
+    Broadcast(String),
+    #[command(description = "Citesc iar tot de pe disc (doar pentru admin)")]
+    Reload,
+    #[command(description = "Salvez tot acum (doar pentru admin)")]
+    Save,
+    #[command(description = "Renunt la ce faceam cu tine")]
+    Anuleaza,
 }
 
-async fn add_command(command: String) -> Result<String, String> {
-    let expression = Expression::from_str(&command);
+async fn add_command(expression: Expression) -> String {
+    let targeted_chat_id = expression.group_id;
+    let mut statemap = STATEMAP.lock().unwrap();
+    let mut trapper = match statemap.remove(&targeted_chat_id) {
+    Some(x) => { x }
+    None    => { Trapper::new() }
+    };
+
+    trapper.commands.push(expression);
     
-    match expression {
-    Err(error) => {
-        Err(error)
-    }  
-    Ok(expression) => {
-        let targeted_chat_id = expression.group_id;
-        let mut statemap = STATEMAP.lock().unwrap();
-        let mut trapper = match statemap.remove(&targeted_chat_id) {
-        Some(x) => { x }
-        None    => { Trapper::new() }
-        };
-
-        trapper.commands.push(expression);
-        
-        statemap.insert(targeted_chat_id, trapper);
-        Ok("Hai ca am adaugat sacale".to_string())
+    statemap.insert(targeted_chat_id, trapper);
+    mark_dirty(targeted_chat_id);
+    "Hai ca am adaugat sacale".to_string()
+}
+
+fn markov_stats(markov: &Markov) -> String {
+    let mut response = format!("Stiu {} cuvinte si {} legaturi intre ele, am invatat din {} \
+                                mesaje si ocup cam {} KB.\n",
+                               markov.vocabulary_size(), markov.edge_count(),
+                               markov.sentences(), markov.serialized_size() / 1024);
+
+    let words = markov.top_words(5);
+    if !words.is_empty() {
+        response += "\nCele mai folosite cuvinte:\n";
+        for (word, count) in words {
+            response += &format!("{} ({})\n", word, count);
+        }
     }
+
+    let transitions = markov.top_transitions(5);
+    if !transitions.is_empty() {
+        response += "\nCele mai folosite perechi:\n";
+        for (from, to, count) in transitions {
+            response += &format!("{} {} ({})\n", from, to, count);
+        }
     }
+
+    response
 }
 
-async fn run_command(command: BotCommands, message: UpdateWithCx<AutoSend<Bot>, Message>) {
-    match command {
-    BotCommands::Joaco => {
-        message.answer("https://www.youtube.com/watch?v=uMUaqROInGk")
-            .await
-            .log_on_error()
-            .await;
+fn markov_next(markov: &Markov, word: &str) -> Result<String, String> {
+    if word.is_empty() {
+        return Err("Zi si tu un cuvant: /dao next [cuvant]".to_string());
     }
-    BotCommands::Taci => {
-        let user_id = if let MessageKind::Common(ref message) = message.update.kind {
-            message.from.as_ref().unwrap().id // if this panics, fuck
-        } else {
-            0
-        };
 
-        if user_id.to_string() == ADMIN_ID.to_string() {
-            message.answer("Bine coaie")
-                .await
-                .log_on_error()
-                .await;
-            exit_program();
-        } else {
-            message.answer("Da nu vrei sa-mi sugi tu pula ca sa taci tu?")
+    let next = markov.next_words(word);
+    if next.is_empty() {
+        return Err(format!("Nu stiu ce vine dupa {}", word));
+    }
+
+    let mut response = format!("Dupa {} vine:\n", word);
+    for (to, probability) in next.into_iter().take(10) {
+        let to = if to.is_empty() { "(gata mesajul)".to_string() } else { to };
+        response += &format!("{} - {:.1}%\n", to, probability * 100.0);
+    }
+
+    Ok(response)
+}
+
+// The chat with the given alias or id, or the current chat if nothing is given
+fn find_chat(target: &str, current: i64) -> Result<i64, String> {
+    let target = target.trim();
+    if target.is_empty() {
+        return Ok(current);
+    }
+
+    match ALIASES.lock().unwrap().get(target) {
+    Some(x) => { Ok(x) }
+    None => {
+        i64::from_str(target).map_err(|_| { WRONG_ALIAS.to_string() })
+    }
+    }
+}
+
+// Whether the sender of the message may change things on another chat: the bot has to
+// know the chat and the sender has to be on it
+async fn check_member(message: &UpdateWithCx<AutoSend<Bot>, Message>,
+                      chat_id: i64) -> Result<(), String> {
+    if chat_id == message.update.chat_id() || sent_by_admin(message) {
+        return Ok(());
+    }
+
+    match message.update.from() {
+    Some(user) => { check_user_member(&message.requester, chat_id, user.id).await }
+    None => { Err(NOT_A_MEMBER.to_string()) }
+    }
+}
+
+// Same as check_member, for any user
+async fn check_user_member(bot: &AutoSend<Bot>, chat_id: i64, user_id: i64) -> Result<(), String> {
+    if ADMIN_IDS.contains(&user_id) {
+        return Ok(());
+    }
+
+    match bot.get_chat_member(chat_id, user_id).await {
+    Ok(member) => {
+        match member.status() {
+        ChatMemberStatus::Left | ChatMemberStatus::Kicked => { Err(NOT_A_MEMBER.to_string()) }
+        _ => { Ok(()) }
+        }
+    }
+    Err(x) => {
+        log::debug!("Failed to find {} on {}: {}", user_id, chat_id, x);
+        Err(UNKNOWN_CHAT.to_string())
+    }
+    }
+}
+
+// Gives the chat one more alias, set by user_id
+fn add_alias(chat_id: i64, alias: &str, user_id: i64) -> String {
+    let alias = match aliases::validate(alias) {
+    Ok(x) => { x }
+    Err(x) => { return x; }
+    };
+
+    let mut aliases = ALIASES.lock().unwrap();
+    match aliases.get(&alias) {
+    Some(id) if id != chat_id => {
+        return "Ce faci sacale, vrei sa furi clout?".to_string();
+    }
+    Some(_) => {
+        return "Ce faci ma, ai pus deja aliasul asta esti prajit?".to_string();
+    }
+    None => {}
+    }
+
+    if aliases.of_chat(chat_id).len() >= aliases::MAX_ALIASES_PER_CHAT {
+        return format!("Aveti deja {} aliasuri, stergeti din ele cu /alias sterge [nume]",
+                       aliases::MAX_ALIASES_PER_CHAT);
+    }
+
+    aliases.insert(&alias, chat_id);
+    mark_aliases_dirty();
+
+    let mut statemap = STATEMAP.lock().unwrap();
+    let trapper = statemap.entry(chat_id).or_insert_with(Trapper::new);
+    trapper.alias_owners.insert(alias.clone(), user_id);
+    mark_dirty(chat_id);
+
+    format!("Gata, de acum va zic si {}", alias)
+}
+
+// Frees an alias of the chat, or of any chat for the admin of the bot
+fn remove_alias(chat_id: i64, alias: &str, any_chat: bool) -> String {
+    let alias = aliases::normalize(alias);
+    let mut aliases = ALIASES.lock().unwrap();
+
+    match aliases.get(&alias) {
+    Some(owner) if owner == chat_id || any_chat => {
+        aliases.remove(&alias);
+        mark_aliases_dirty();
+
+        if let Some(trapper) = STATEMAP.lock().unwrap().get_mut(&owner) {
+            trapper.alias_owners.remove(&alias);
+            mark_dirty(owner);
+        }
+
+        format!("Am sters aliasul {}", alias)
+    }
+    Some(_) => { "Aliasul ala nu e al vostru".to_string() }
+    None => { "Nu stiu aliasul asta".to_string() }
+    }
+}
+
+// The role the user has on the chat: the one given with /rol, raised to moderator for the
+// admins of the group and to owner for its creator. The other side of a private chat and
+// the admins of the bot are owners
+async fn user_role(bot: &AutoSend<Bot>, chat_id: i64, user_id: i64) -> Role {
+    if ADMIN_IDS.contains(&user_id) || chat_id == user_id {
+        return Role::Owner;
+    }
+
+    let stored = stored_role(chat_id, user_id);
+    let telegram = match bot.get_chat_member(chat_id, user_id).await {
+    Ok(member) => {
+        match member.status() {
+        ChatMemberStatus::Creator => { Some(Role::Owner) }
+        ChatMemberStatus::Administrator => { Some(Role::Moderator) }
+        _ => { None }
+        }
+    }
+    Err(x) => {
+        log::debug!("Failed to find {} on {}: {}", user_id, chat_id, x);
+        None
+    }
+    };
+
+    telegram.map_or(stored, |x| { x.max(stored) })
+}
+
+// The role given with /rol
+fn stored_role(chat_id: i64, user_id: i64) -> Role {
+    STATEMAP.lock().unwrap()
+        .get(&chat_id)
+        .map(|trapper| { trapper.role_of(user_id) })
+        .unwrap_or_default()
+}
+
+// Same as user_role, for the sender of the message. Returns the id of the sender too
+async fn sender_role(message: &UpdateWithCx<AutoSend<Bot>, Message>,
+                     chat_id: i64) -> Result<(i64, Role), String> {
+    match message.update.from() {
+    Some(user) => { Ok((user.id, user_role(&message.requester, chat_id, user.id).await)) }
+    None => { Err(NOT_A_MEMBER.to_string()) }
+    }
+}
+
+// Whether the user may do the action on the chat. Telegram is only asked when the role
+// given with /rol isn't enough
+async fn check_user_permission(bot: &AutoSend<Bot>, chat_id: i64, user_id: i64,
+                               action: Action) -> Result<(), String> {
+    if action.allowed(stored_role(chat_id, user_id)) {
+        return Ok(());
+    }
+
+    match user_role(bot, chat_id, user_id).await {
+    role if action.allowed(role) => { Ok(()) }
+    role => { Err(not_allowed(role, action)) }
+    }
+}
+
+// Same as check_user_permission, for the sender of the message. Returns the id of the
+// sender
+async fn check_permission(message: &UpdateWithCx<AutoSend<Bot>, Message>, chat_id: i64,
+                          action: Action) -> Result<i64, String> {
+    let user_id = match message.update.from() {
+    Some(user) => { user.id }
+    None => { return Err(NOT_A_MEMBER.to_string()); }
+    };
+
+    check_user_permission(&message.requester, chat_id, user_id, action).await
+        .map(|()| { user_id })
+}
+
+fn not_allowed(role: Role, action: Action) -> String {
+    if role == Role::Banned {
+        BANNED.to_string()
+    } else {
+        format!("Trebuie sa fii macar {} ca sa faci asta", action.needed_role().name())
+    }
+}
+
+// /taci [cit] on a group: the triggers don't answer for that long, "gata" makes them
+// answer again
+fn mute_chat(chat_id: i64, how_long: &str) -> String {
+    let mut statemap = STATEMAP.lock().unwrap();
+    let trapper = statemap.entry(chat_id).or_insert_with(Trapper::new);
+
+    if how_long.eq_ignore_ascii_case("gata") {
+        trapper.unmute();
+        mark_dirty(chat_id);
+        return "Gata, am iar chef de vorba".to_string();
+    }
+
+    let duration = match humantime::parse_duration(how_long) {
+    Ok(x) if x.as_secs() >= 60 && x <= MAX_MUTE => { x }
+    Ok(_) => {
+        return format!("Pot sa tac intre un minut si {}", humantime::format_duration(MAX_MUTE));
+    }
+    Err(_) => { return HELP_TACI.to_string(); }
+    };
+
+    trapper.mute(trapper::now(), duration.as_secs());
+    mark_dirty(chat_id);
+    format!("Bine, tac din gura {}", humantime::format_duration(duration))
+}
+
+fn chat_settings(chat_id: i64) -> GroupSettings {
+    STATEMAP.lock().unwrap()
+        .get(&chat_id)
+        .map(|trapper| { trapper.settings.clone() })
+        .unwrap_or_default()
+}
+
+fn change_setting(chat_id: i64, setting: Setting, value: &str) -> String {
+    let mut statemap = STATEMAP.lock().unwrap();
+    let trapper = statemap.entry(chat_id).or_insert_with(Trapper::new);
+
+    match trapper.settings.set(setting, value) {
+    Ok(()) => {
+        mark_dirty(chat_id);
+        format!("Gata, {} e acum {}", setting.name(), trapper.settings.get(setting))
+    }
+    Err(x) => { x }
+    }
+}
+
+// The text and the buttons of /setari, a button for every setting. Pressing one moves the
+// setting to its next value
+fn settings_menu(settings: &GroupSettings) -> (String, InlineKeyboardMarkup) {
+    let title = "Setarile grupului, apasa pe una ca s-o schimbi:";
+
+    let buttons = SETTINGS.iter().map(|setting| {
+        vec![InlineKeyboardButton::callback(
+            format!("{}: {}", setting.label(), settings.get(*setting)),
+            format!("{}{}", SETTINGS_CALLBACK, setting.name()))]
+    });
+
+    (title.to_string(), InlineKeyboardMarkup::new(buttons))
+}
+
+// A button of the /setari menu was pressed
+async fn process_callback(query: UpdateWithCx<AutoSend<Bot>, CallbackQuery>) {
+    let setting = query.update.data.as_deref()
+        .and_then(|x| { x.strip_prefix(SETTINGS_CALLBACK) })
+        .and_then(Setting::from_name);
+
+    let error = match (setting, &query.update.message) {
+    (Some(setting), Some(menu)) => {
+        let chat_id = menu.chat.id;
+        let allowed = check_user_permission(&query.requester, chat_id, query.update.from.id,
+                                            Action::ChangeSettings).await;
+
+        match allowed {
+        Ok(()) => {
+            let (text, keyboard) = {
+                let mut statemap = STATEMAP.lock().unwrap();
+                let trapper = statemap.entry(chat_id).or_insert_with(Trapper::new);
+                trapper.settings.cycle(setting);
+                mark_dirty(chat_id);
+
+                settings_menu(&trapper.settings)
+            };
+
+            query.requester.edit_message_text(chat_id, menu.id, text)
+                .reply_markup(keyboard)
                 .await
                 .log_on_error()
                 .await;
+            None
+        }
+        Err(x) => { Some(x) }
         }
     }
-    BotCommands::Adauga(command) => {
-        let chat_id = message.update.chat_id();
-        let tokens: Vec<String> = command.split("~").map(|x| { x.to_string() }).collect();
-        let command = if let MessageKind::Common(ref message) = message.update.kind {
-            let user_id = message.from.as_ref().unwrap().id; // If this panics, fuck
-            if tokens.len() == 3 {
-                Ok(user_id.to_string() + &"~" + &command)
-            } else if tokens.len() == 2 {
-                match ALIASES.lock().unwrap().get_by_right(&chat_id) {
-                Some(x) => { Ok(user_id.to_string() + &"~" + x + &"~" + &tokens[0] + 
-                                                                 &"~" + &tokens[1]) }
-                None    => { Err(WRONG_ALIAS.to_string()) }
-                }
-            } else {
-                Err(BAD_SEPARATORS.to_string())
-            }
-        } else {
-            Err("Ce plm mi-ai trimis aici".to_string())
-        };
-        
-        let result_command = match command {
-        Ok(command) => {
-            match add_command(command).await { Ok(x) => {x} Err(x) => {x} }
+    _ => { None }
+    };
+
+    let mut answer = query.requester.answer_callback_query(query.update.id.clone());
+    if let Some(error) = error {
+        answer = answer.text(error).show_alert(true);
+    }
+    answer.await.log_on_error().await;
+}
+
+// Totals over every group, for /stats
+fn bot_stats() -> String {
+    let aliases = ALIASES.lock().unwrap().count();
+    let statemap = STATEMAP.lock().unwrap();
+
+    let mut commands = 0;
+    let mut thoughts = 0;
+    let mut scheduled = 0;
+    let mut words = 0;
+    let mut edges = 0;
+    let mut sentences = 0;
+    let mut size = 0;
+    for trapper in statemap.values() {
+        commands += trapper.commands.len();
+        thoughts += trapper.thoughts.len();
+        scheduled += trapper.scheduled_thoughts.len();
+        words += trapper.markov.vocabulary_size();
+        edges += trapper.markov.edge_count();
+        sentences += trapper.markov.sentences();
+        size += trapper.markov.serialized_size();
+    }
+
+    format!("Sunt pe {} grupuri cu {} aliasuri.\n\
+             Stiu {} comenzi, {} ginduri si inca {} programate.\n\
+             Pentru /dao stiu {} cuvinte si {} legaturi, din {} mesaje, cam {} KB.",
+            statemap.len(), aliases, commands, thoughts, scheduled, words, edges, sentences,
+            size / 1024)
+}
+
+// Every chat the bot knows, with its aliases, for /groups
+fn list_groups() -> String {
+    let aliases = ALIASES.lock().unwrap();
+    let statemap = STATEMAP.lock().unwrap();
+
+    let mut chats: Vec<i64> = statemap.keys().copied()
+        .chain(aliases.pairs().into_iter().map(|x| { x.1 }))
+        .collect();
+    chats.sort_unstable();
+    chats.dedup();
+
+    if chats.is_empty() {
+        return "Nu sunt pe niciun grup".to_string();
+    }
+
+    let mut response = String::new();
+    for (index, chat_id) in chats.iter().enumerate() {
+        let names: Vec<&str> = aliases.of_chat(*chat_id).into_iter().map(|x| { x.as_str() }).collect();
+        let commands = statemap.get(chat_id).map(|x| { x.commands.len() }).unwrap_or(0);
+        let line = match names.as_slice() {
+        [] => { format!("{} ({} comenzi)\n", chat_id, commands) }
+        names => { format!("{}: {} ({} comenzi)\n", chat_id, names.join(", "), commands) }
+        };
+
+        if response.len() + line.len() > MAX_MESSAGE_LENGTH {
+            response += &format!("... si inca {}", chats.len() - index);
+            break;
+        }
+        response += &line;
+    }
+
+    response
+}
+
+// Sends the text on every group the bot knows. Returns on how many it worked and on how
+// many it didn't
+// Only to the groups, the private chats the bot knows are people
+async fn broadcast(bot: &AutoSend<Bot>, text: &str) -> (usize, usize) {
+    let chats: Vec<i64> = STATEMAP.lock().unwrap().keys().copied()
+        .filter(|chat_id| { *chat_id < 0 })
+        .collect();
+    let mut sent = 0;
+    let mut failed = 0;
+
+    for chat_id in chats {
+        match bot.send_message(chat_id, text).await {
+        Ok(_) => { sent += 1; }
+        Err(x) => {
+            log::warn!("Failed to broadcast on {}: {}", chat_id, x);
+            failed += 1;
+        }
+        }
+
+        // Telegram doesn't like more than about 30 messages a second
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    (sent, failed)
+}
+
+// The triggers numbered from 1, as /comenzi sterge takes them. Telegram doesn't send
+// messages that are too long, so the list stops before that
+fn list_commands(commands: &[Expression]) -> String {
+    let mut response = String::new();
+
+    for (index, command) in commands.iter().enumerate() {
+        let line = format!("{}. {}\n", index + 1, command.describe());
+        if response.len() + line.len() > MAX_MESSAGE_LENGTH {
+            response += &format!("... si inca {}", commands.len() - index);
+            break;
+        }
+        response += &line;
+    }
+
+    response
+}
+
+fn remove_command(chat_id: i64, number: &str) -> String {
+    let index = match usize::from_str(number) {
+    Ok(x) if x > 0 => { x - 1 }
+    _ => { return "Zi-mi numarul comenzii din /comenzi".to_string(); }
+    };
+
+    let mut statemap = STATEMAP.lock().unwrap();
+    match statemap.get_mut(&chat_id) {
+    Some(trapper) if index < trapper.commands.len() => {
+        let removed = trapper.commands.remove(index);
+        mark_dirty(chat_id);
+        format!("Am sters {}", removed.describe())
+    }
+    _ => { "Nu am comanda asta".to_string() }
+    }
+}
+
+// The users with a role other than member
+fn list_roles(chat_id: i64) -> String {
+    let statemap = STATEMAP.lock().unwrap();
+    let trapper = match statemap.get(&chat_id) {
+    Some(x) if !x.roles.is_empty() => { x }
+    _ => { return "Restul sunt membri".to_string(); }
+    };
+
+    let mut roles: Vec<(Role, String)> = trapper.roles.iter()
+        .map(|(user_id, role)| { (*role, trapper.user_name(*user_id)) })
+        .collect();
+    roles.sort_by(|x, y| { y.cmp(x) });
+
+    let mut response = String::new();
+    for (role, name) in roles {
+        response += &format!("{} e {}\n", name, role.name());
+    }
+    response
+}
+
+// The user a command is about: the sender of the message replied to, or the first word
+// as @username or id, which is taken out of the tokens. None if there's neither
+fn target_user(message: &UpdateWithCx<AutoSend<Bot>, Message>,
+               tokens: &mut Vec<&str>) -> Result<Option<i64>, String> {
+    let replied = message.update.reply_to_message().and_then(|x| { x.from() }).map(|x| { x.id });
+
+    match replied {
+    Some(x) => { Ok(Some(x)) }
+    None if tokens.is_empty() => { Ok(None) }
+    None => {
+        let target = tokens.remove(0);
+        let known = STATEMAP.lock().unwrap()
+            .get(&message.update.chat_id())
+            .and_then(|trapper| { trapper.find_user(target) });
+
+        match known {
+        Some(x) => { Ok(Some(x)) }
+        None => { i64::from_str(target).map(Some).map_err(|_| { DAO_UNKNOWN_USER.to_string() }) }
+        }
+    }
+    }
+}
+
+// Who /rol is about and the role to give them, if any
+fn parse_rol(message: &UpdateWithCx<AutoSend<Bot>, Message>,
+             args: &str) -> Result<(Option<i64>, Option<Role>), String> {
+    let mut tokens: Vec<&str> = args.split_whitespace().collect();
+    let user_id = target_user(message, &mut tokens)?;
+
+    match tokens.as_slice() {
+    [] => { Ok((user_id, None)) }
+    [role] => {
+        match Role::from_name(role) {
+        Some(role) => { Ok((user_id, Some(role))) }
+        None => { Err(format!("Nu stiu rolul {}, alege banat, membru, moderator sau sef", role)) }
+        }
+    }
+    _ => { Err(HELP_ROL.to_string()) }
+    }
+}
+
+async fn set_role(message: &UpdateWithCx<AutoSend<Bot>, Message>, user_id: i64,
+                  role: Role) -> String {
+    let chat_id = message.update.chat_id();
+    let my_role = match sender_role(message, chat_id).await {
+    Ok((_, x)) if Action::ManageRoles.allowed(x) => { x }
+    Ok((_, x)) => { return not_allowed(x, Action::ManageRoles); }
+    Err(x) => { return x; }
+    };
+
+    let mut statemap = STATEMAP.lock().unwrap();
+    let trapper = statemap.entry(chat_id).or_insert_with(Trapper::new);
+    let current = trapper.role_of(user_id);
+
+    if !my_role.can_change(current, role) {
+        return format!("Nu poti sa faci un {} {}", current.name(), role.name());
+    }
+
+    trapper.set_role(user_id, role);
+    mark_dirty(chat_id);
+    format!("Gata, {} e {}", trapper.user_name(user_id), role.name())
+}
+
+// Sends a file in private to whoever sent the message
+async fn send_in_private(message: &UpdateWithCx<AutoSend<Bot>, Message>, file_name: String,
+                         content: String) -> Result<(), String> {
+    let user_id = match message.update.from() {
+    Some(user) => { user.id }
+    None => { return Err(NO_PRIVATE_CHAT.to_string()); }
+    };
+
+    match message.requester.send_document(user_id, InputFile::memory(file_name, content.into_bytes())).await {
+    Ok(_) => { Ok(()) }
+    Err(x) => {
+        log::warn!("Failed to send a file to {}: {}", user_id, x);
+        Err(NO_PRIVATE_CHAT.to_string())
+    }
+    }
+}
+
+// Downloads the document the message replies to
+async fn download_reply(message: &UpdateWithCx<AutoSend<Bot>, Message>) -> Result<String, String> {
+    let document = match message.update.reply_to_message().and_then(|x| { x.document() }) {
+    Some(x) => { x }
+    None => { return Err(IMPORT_NO_DOCUMENT.to_string()); }
+    };
+
+    if document.file_size.unwrap_or(0) as u64 > *MAX_IMPORT_BYTES {
+        return Err(IMPORT_TOO_BIG.to_string());
+    }
+
+    let file = message.requester.get_file(document.file_id.clone())
+        .await
+        .map_err(|x| { format!("Nu pot sa iau fisierul: {}", x) })?;
+
+    let mut content = Vec::new();
+    message.requester.inner().download_file(&file.file_path, &mut content)
+        .await
+        .map_err(|x| { format!("Nu pot sa descarc fisierul: {}", x) })?;
+
+    String::from_utf8(content).map_err(|_| { "Fisierul nu e text".to_string() })
+}
+
+fn sent_by_admin(message: &UpdateWithCx<AutoSend<Bot>, Message>) -> bool {
+    message.update.from().is_some_and(|user| { ADMIN_IDS.contains(&user.id) })
+}
+
+async fn run_command(command: BotCommands, message: UpdateWithCx<AutoSend<Bot>, Message>) {
+    match command {
+    BotCommands::Joaco => {
+        message.answer(JOACO_LINK)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Taci(how_long) if !how_long.trim().is_empty() => {
+        let chat_id = message.update.chat_id();
+        let response = match check_permission(&message, chat_id, Action::Mute).await {
+        Ok(_) => { mute_chat(chat_id, how_long.trim()) }
+        Err(x) => { x }
+        };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Taci(_) => {
+        if sent_by_admin(&message) {
+            message.answer("Bine coaie")
+                .await
+                .log_on_error()
+                .await;
+            request_shutdown();
+        } else {
+            message.answer("Da nu vrei sa-mi sugi tu pula ca sa taci tu?")
+                .await
+                .log_on_error()
+                .await;
+        }
+    }
+    BotCommands::Adauga(command) => {
+        let chat_id = message.update.chat_id();
+        let tokens: Vec<String> = command.split("~").map(|x| { x.to_string() }).collect();
+        let command = if let MessageKind::Common(ref message) = message.update.kind {
+            let user_id = message.from.as_ref().unwrap().id; // If this panics, fuck
+            if tokens.len() == 3 {
+                Ok(user_id.to_string() + "~" + &command)
+            } else if tokens.len() == 2 {
+                match ALIASES.lock().unwrap().of_chat(chat_id).first() {
+                Some(x) => { Ok(user_id.to_string() + "~" + x + "~" + &tokens[0] + 
+                                                                 "~" + &tokens[1]) }
+                None    => { Err(WRONG_ALIAS.to_string()) }
+                }
+            } else {
+                Err(BAD_SEPARATORS.to_string())
+            }
+        } else {
+            Err("Ce plm mi-ai trimis aici".to_string())
+        };
+        
+        // Triggers for another group only from its members
+        let result_command = match command.and_then(|x| { Expression::from_str(&x) }) {
+        Ok(expression) => {
+            let allowed = match check_member(&message, expression.group_id).await {
+            Ok(()) => { check_permission(&message, expression.group_id, Action::AddTrigger).await }
+            Err(x) => { Err(x) }
+            };
+
+            match allowed {
+            Ok(_) => { add_command(expression).await }
+            Err(x) => { x }
+            }
+        }
+        Err(x) => { x }
+        };
+        
+        message.answer(result_command)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Help(with_what) => {
+        let help_message = match with_what.trim().to_lowercase().as_str() {
+        HELP_ADAUGA_TAB => {
+            HELP_ADAUGA
+        }
+        HELP_TACI_TAB => {
+            HELP_TACI
+        }
+        HELP_JOACO_TAB => {
+            HELP_JOACO
+        }
+        HELP_HELP_TAB => {
+            HELP_HELP
+        }
+        HELP_ALIAS_TAB => {
+            HELP_ALIAS
+        }
+        HELP_GIND_TAB => {
+            HELP_GIND
+        }
+        HELP_DAO_TAB => {
+            HELP_DAO
+        }
+        HELP_COMENZI_TAB => {
+            HELP_COMENZI
+        }
+        HELP_SETARI_TAB => {
+            HELP_SETARI
+        }
+        HELP_ROL_TAB => {
+            HELP_ROL
+        }
+        HELP_CEVA_TAB => {
+            HELP_CEVA
+        }
+        HELP_ADMIN_TAB => {
+            HELP_ADMIN
+        }
+        _ => {
+            HELP_DEFAULT
+        }
+        };
+
+        message.answer(help_message)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Alias(alias) => {
+        let chat_id = message.update.chat_id();
+        let (what, rest) = match alias.trim().split_once(char::is_whitespace) {
+        Some((what, rest)) => { (what.to_lowercase(), rest.trim()) }
+        None => { (alias.trim().to_lowercase(), "") }
+        };
+
+        let response = match what.as_str() {
+        "" => {
+            let mut response = format!("Uite aici id-ul chatului sacale: {}\n", chat_id);
+            let aliases = ALIASES.lock().unwrap();
+            let statemap = STATEMAP.lock().unwrap();
+
+            match aliases.of_chat(chat_id).as_slice() {
+            [] => { response += "N-aveti niciun alias, puneti unul cu /alias [nume]"; }
+            names => {
+                response += "Uite aici aliasurile chatului sacale:\n";
+                for name in names {
+                    let owner = statemap.get(&chat_id)
+                        .and_then(|trapper| { trapper.alias_owner(name) })
+                        .map(|x| { format!(" (l-a pus {})", x) })
+                        .unwrap_or_default();
+                    response += &format!("{}{}\n", name, owner);
+                }
+            }
+            }
+            response
+        }
+        "sterge" => match check_permission(&message, chat_id, Action::ChangeSettings).await {
+        Err(x) => { x }
+        Ok(_) => { remove_alias(chat_id, rest, sent_by_admin(&message)) }
+        }
+        _ => match check_permission(&message, chat_id, Action::ChangeSettings).await {
+        Err(x) => { x }
+        Ok(user_id) => { add_alias(chat_id, &alias, user_id) }
+        }
+        };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Comenzi(what) => {
+        let chat_id = message.update.chat_id();
+        let (what, rest) = match what.trim().split_once(char::is_whitespace) {
+        Some((what, rest)) => { (what.to_lowercase(), rest.trim().to_string()) }
+        None => { (what.trim().to_lowercase(), String::new()) }
+        };
+
+        let response = match what.as_str() {
+        "" => {
+            let statemap = STATEMAP.lock().unwrap();
+            match statemap.get(&chat_id).map(|x| { x.commands.as_slice() }).unwrap_or_default() {
+            [] => { "N-aveti nicio comanda, adaugati una cu /adauga".to_string() }
+            commands => { list_commands(commands) }
+            }
+        }
+        "sterge" => {
+            match check_permission(&message, chat_id, Action::DeleteTrigger).await {
+            Ok(_) => { remove_command(chat_id, &rest) }
+            Err(x) => { x }
+            }
+        }
+        _ => {
+            HELP_COMENZI.to_string()
+        }
+        };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Rol(args) => {
+        let response = match parse_rol(&message, &args) {
+        Ok((None, _)) => {
+            let chat_id = message.update.chat_id();
+            match sender_role(&message, chat_id).await {
+            Ok((_, role)) => { format!("Esti {} aici\n{}", role.name(), list_roles(chat_id)) }
+            Err(x) => { x }
+            }
+        }
+        Ok((Some(user_id), None)) => {
+            let statemap = STATEMAP.lock().unwrap();
+            match statemap.get(&message.update.chat_id()) {
+            Some(trapper) => {
+                format!("{} e {}", trapper.user_name(user_id), trapper.role_of(user_id).name())
+            }
+            None => { format!("{} e {}", user_id, Role::Member.name()) }
+            }
+        }
+        Ok((Some(user_id), Some(role))) => { set_role(&message, user_id, role).await }
+        Err(x) => { x }
+        };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Setari(args) => {
+        let chat_id = message.update.chat_id();
+        let tokens: Vec<&str> = args.split_whitespace().collect();
+
+        let response = match tokens.as_slice() {
+        [] => {
+            let (text, keyboard) = settings_menu(&chat_settings(chat_id));
+            message.answer(text)
+                .reply_markup(keyboard)
+                .await
+                .log_on_error()
+                .await;
+            return;
+        }
+        [name, value] => {
+            match Setting::from_name(name) {
+            Some(setting) => {
+                match check_permission(&message, chat_id, Action::ChangeSettings).await {
+                Ok(_) => { change_setting(chat_id, setting, value) }
+                Err(x) => { x }
+                }
+            }
+            None => { HELP_SETARI.to_string() }
+            }
+        }
+        _ => { HELP_SETARI.to_string() }
+        };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Gindeste(gind) => {
+        let now = trapper::now();
+        let args = parse_gindeste(&gind, message.update.chat_id(), &ALIASES.lock().unwrap(), now,
+                                  *UTC_OFFSET);
+
+        let args = match args {
+        Ok(args) => { check_member(&message, args.chat_id).await.map(|()| { args }) }
+        Err(x) => { Err(x) }
+        };
+        let args = match args {
+        Ok(args) => {
+            check_permission(&message, args.chat_id, Action::AddThought).await.map(|_| { args })
+        }
+        Err(x) => { Err(x) }
+        };
+
+        let response = match args {
+        Ok(args) => {
+            let chat_id = args.chat_id;
+            let mut statemap = STATEMAP.lock().unwrap();
+            let mut trapper = match statemap.remove(&chat_id) {
+            None => {
+                trapper::Trapper::new()
+            }
+            Some(x) => { x }
+            };
+        
+            let author = message.update.from().map(|x| { x.id }).unwrap_or(0);
+            let response = match args.deliver_at {
+            Some(at) => {
+                trapper.schedule_thought(Thought::new(args.text, author, now, 0), at);
+                format!("Am programat gindul, il zic peste {}",
+                        humantime::format_duration(Duration::from_secs(at - now)))
+            }
+            None => {
+                trapper.thoughts.push(Thought::new(args.text, author, now, *THOUGHT_LIFETIME));
+                "Am bagat un gind frumos".to_string()
+            }
+            };
+            statemap.insert(chat_id, trapper);
+            mark_dirty(chat_id);
+            response
+        }
+        Err(x) => {
+            x
+        }
+        };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Gind(what) => {
+        let chat_id = message.update.chat_id();
+        let (what, rest) = match what.trim().split_once(char::is_whitespace) {
+        Some((what, rest)) => { (what.to_lowercase(), rest.trim().to_string()) }
+        None => { (what.trim().to_lowercase(), String::new()) }
+        };
+
+        let response = match what.as_str() {
+        "" => {
+            let mut statemap = STATEMAP.lock().unwrap();
+            match statemap.get_mut(&chat_id) {
+            Some(trapper) => {
+                // The group is only saved again if some thought expired or was taken
+                let now = trapper::now();
+                let expired = trapper.drop_expired_thoughts(now);
+                let gind = trapper.take_thought(&mut rand::thread_rng());
+                if expired > 0 || gind.is_some() {
+                    mark_dirty(chat_id);
+                }
+
+                match gind {
+                Some(x) => { x.reveal(now) }
+                None => { GIND_EMPTY.to_string() }
+                }
+            }
+            None => { GIND_EMPTY.to_string() }
+            }
+        }
+        "count" => {
+            let mut statemap = STATEMAP.lock().unwrap();
+            match statemap.get_mut(&chat_id) {
+            Some(trapper) => {
+                if trapper.drop_expired_thoughts(trapper::now()) > 0 {
+                    mark_dirty(chat_id);
+                }
+
+                let scheduled = match trapper.scheduled_thoughts.len() {
+                0 => { String::new() }
+                x => { format!(", plus {} programate", x) }
+                };
+
+                match trapper.thoughts.len() {
+                0 => { format!("{}{}", GIND_EMPTY, scheduled) }
+                1 => { format!("Mai am un singur gind{}", scheduled) }
+                x => { format!("Mai am {} ginduri{}", x, scheduled) }
+                }
+            }
+            None => { GIND_EMPTY.to_string() }
+            }
+        }
+        "mod" if !rest.is_empty() => {
+            match check_permission(&message, chat_id, Action::ChangeSettings).await {
+            Ok(_) => {
+                match ThoughtOrder::from_name(&rest) {
+                Some(order) => {
+                    let mut statemap = STATEMAP.lock().unwrap();
+                    statemap.entry(chat_id).or_insert_with(Trapper::new).thought_order = order;
+                    mark_dirty(chat_id);
+                    format!("Gata, de acum dau gindurile in ordinea {}", order.name())
+                }
+                None => {
+                    format!("Nu stiu ordinea {}, alege random, fifo sau lifo", rest)
+                }
+                }
+            }
+            Err(x) => { x }
+            }
+        }
+        "mod" => {
+            let statemap = STATEMAP.lock().unwrap();
+            let order = statemap.get(&chat_id).map(|x| { x.thought_order }).unwrap_or_default();
+
+            format!("Acum dau gindurile in ordinea {}", order.name())
+        }
+        "sterge" if !sent_by_admin(&message) => {
+            ONLY_ADMIN.to_string()
+        }
+        "sterge" => {
+            let target = match rest.split_once('~') {
+            Some((alias, what)) => { find_chat(alias, chat_id).map(|x| { (x, what) }) }
+            None => { Ok((chat_id, rest.as_str())) }
+            };
+
+            match target {
+            Ok((chat_id, what)) => {
+                let mut statemap = STATEMAP.lock().unwrap();
+                let removed = match statemap.get_mut(&chat_id) {
+                Some(trapper) => { trapper.remove_thoughts(what) }
+                None => { 0 }
+                };
+
+                if removed > 0 {
+                    mark_dirty(chat_id);
+                    format!("Am aruncat {} ginduri", removed)
+                } else {
+                    "N-am gasit niciun gind de aruncat".to_string()
+                }
+            }
+            Err(x) => { x }
+            }
+        }
+        _ => {
+            HELP_GIND.to_string()
+        }
+        };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Dao(who) => {
+        let chat_id = message.update.chat_id();
+        let allowed = check_permission(&message, chat_id, Action::Dao).await;
+        let response = match allowed {
+        Err(x) => { Err(x) }
+        Ok(_) => {
+            let mut statemap = STATEMAP.lock().unwrap();
+            let mut trapper = match statemap.remove(&chat_id) {
+                None => { trapper::Trapper::new() }
+                Some(x) => { x }
+            };
+
+            let (what, rest) = match who.trim().split_once(char::is_whitespace) {
+            Some((what, rest)) => { (what, rest.trim()) }
+            None => { (who.trim(), "") }
+            };
+
+            let response = match what {
+            "" => {
+                trapper.markov.get_random().ok_or_else(|| DAO_EMPTY.to_string())
+            }
+            "stats" => {
+                Ok(markov_stats(&trapper.markov))
+            }
+            "next" => {
+                markov_next(&trapper.markov, rest)
+            }
+            username => {
+                match trapper.find_user(username) {
+                None => { Err(DAO_UNKNOWN_USER.to_string()) }
+                Some(user_id) if !trapper.can_imitate(user_id) => {
+                    Err(DAO_NO_IMITATION.to_string())
+                }
+                Some(user_id) => {
+                    trapper.get_random_from(user_id).ok_or_else(|| DAO_EMPTY.to_string())
+                }
+                }
+            }
+            };
+            statemap.insert(chat_id, trapper);
+
+            response
+        }
+        };
+
+        let response = match response { Ok(x) => { x } Err(x) => { x } };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::NuMaImita | BotCommands::ImitaMa => {
+        let allowed = matches!(command, BotCommands::ImitaMa);
+        let chat_id = message.update.chat_id();
+        let response = match message.update.from() {
+        Some(user) => {
+            let mut statemap = STATEMAP.lock().unwrap();
+            let mut trapper = match statemap.remove(&chat_id) {
+                None => { trapper::Trapper::new() }
+                Some(x) => { x }
+            };
+
+            trapper.set_imitation(user.id, allowed);
+            statemap.insert(chat_id, trapper);
+            mark_dirty(chat_id);
+
+            if allowed { IMITATION_ON } else { IMITATION_OFF }
         }
-        Err(x) => { x }
+        None => { "Ce plm mi-ai trimis aici" }
         };
-        
-        message.answer(result_command)
+
+        message.answer(response)
             .await
             .log_on_error()
             .await;
     }
-    BotCommands::Help(with_what) => {
-        let help_message = match with_what.trim().to_lowercase().as_str() {
-        HELP_ADAUGA_TAB => {
-            HELP_ADAUGA
-        }
-        HELP_TACI_TAB => {
-            HELP_TACI
-        }
-        HELP_JOACO_TAB => {
-            HELP_JOACO
-        }
-        HELP_HELP_TAB => {
-            HELP_HELP
-        }
-        HELP_ALIAS_TAB => {
-            HELP_ALIAS
+    BotCommands::NuMaInvata | BotCommands::InvataMa => {
+        let allowed = matches!(command, BotCommands::InvataMa);
+        let chat_id = message.update.chat_id();
+        let response = match message.update.from() {
+        Some(user) => {
+            let mut statemap = STATEMAP.lock().unwrap();
+            let trapper = statemap.entry(chat_id).or_insert_with(Trapper::new);
+
+            trapper.set_training(user.id, allowed);
+            mark_dirty(chat_id);
+
+            if allowed { TRAINING_ON } else { TRAINING_OFF }
         }
-        HELP_GIND_TAB => {
-            HELP_GIND
+        None => { "Ce plm mi-ai trimis aici" }
+        };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::UitaMa(who) => {
+        let chat_id = message.update.chat_id();
+        let mut tokens: Vec<&str> = who.split_whitespace().collect();
+
+        // Anyone may take themselves out, the moderators may take out anyone
+        let target = match (target_user(&message, &mut tokens), message.update.from()) {
+        (Ok(Some(user_id)), Some(user)) if user_id != user.id => {
+            check_permission(&message, chat_id, Action::ForgetUser).await.map(|_| { user_id })
         }
-        _ => {
-            HELP_DEFAULT
+        (Ok(_), Some(user)) => { Ok(user.id) }
+        (Ok(_), None) => { Err("Ce plm mi-ai trimis aici".to_string()) }
+        (Err(x), _) => { Err(x) }
+        };
+
+        let response = match target {
+        Ok(user_id) => {
+            let mut statemap = STATEMAP.lock().unwrap();
+            let forgotten = statemap.get_mut(&chat_id)
+                .filter(|trapper| { trapper.user_markov.contains_key(&user_id) });
+
+            match forgotten {
+            Some(trapper) => {
+                trapper.forget_user(user_id);
+                mark_dirty(chat_id);
+                format!("Am uitat tot ce am invatat de la {}", trapper.user_name(user_id))
+            }
+            None => { "Nu stiam nimic de la el oricum".to_string() }
+            }
         }
+        Err(x) => { x }
         };
 
-        message.answer(help_message)
+        message.answer(response)
             .await
             .log_on_error()
             .await;
     }
-    BotCommands::Alias(alias) => {
-        let response = match alias.as_str() {
-        "" => {
-            let chat_id = message.update.chat_id();
-            let mut response = format!("Uite aici id-ul chatului sacale: {}\n", chat_id);
-            response = response + &match ALIASES.lock().unwrap().get_by_right(&chat_id) {
-            None => { String::new() }
-            Some(x) => { format!("Uite aici aliasul chatului sacale: {}", x) }
-            };
-            response
-        }
-        _ => {
-            let chat_id = message.update.chat_id();
-            let mut aliases = ALIASES.lock().unwrap();
-            
-            match { aliases.get_by_left(&alias) } {
-            Some(id) => {
-                if *id != chat_id {
-                    "Ce faci sacale, vrei sa furi clout?".to_string()
-                } else {
-                    "Ce faci ma, ai pus deja aliasul asta esti prajit?".to_string()
+    BotCommands::Uita(what) => {
+        let response = if !sent_by_admin(&message) {
+            "Uita-te tu in oglinda".to_string()
+        } else {
+            let tokens: Vec<&str> = what.split('~').collect();
+            let target = match tokens.len() {
+            1 => { Ok((message.update.chat_id(), tokens[0])) }
+            2 => {
+                match ALIASES.lock().unwrap().get(tokens[0]) {
+                Some(x) => { Ok((x, tokens[1])) }
+                None    => { Err(WRONG_ALIAS.to_string()) }
                 }
             }
-            None => {
-                let ans = format!("Ti-am schimbat aliasul in: {}", alias);
-                aliases.remove_by_right(&chat_id);
-                aliases.insert(alias, chat_id);
-                ans
+            _ => { Err(BAD_SEPARATORS.to_string()) }
+            };
+
+            match target {
+            Ok((chat_id, word)) => {
+                let mut statemap = STATEMAP.lock().unwrap();
+                match statemap.get_mut(&chat_id) {
+                Some(trapper) => {
+                    match trapper.forget_word(word) {
+                    0 => { "Nu stiam cuvantul asta oricum".to_string() }
+                    _ => {
+                        mark_dirty(chat_id);
+                        format!("Am uitat de {}", word.trim())
+                    }
+                    }
+                }
+                None => { "Nu stiu nimic despre grupul asta".to_string() }
+                }
             }
+            Err(x) => { x }
             }
-        }
         };
+
         message.answer(response)
             .await
             .log_on_error()
             .await;
     }
-    BotCommands::Gindeste(gind) => {
-        let tokens: Vec<String> = gind.split("~").map(|x| {x.to_string()}).collect();
-        
-        let result: Result<(i64, String), String> = match tokens.len() {
-        0 => {
-            Ok((message.update.chat_id(), String::new()))
-        }
-        1 => {
-            Ok((message.update.chat_id(), tokens[0].clone()))
+    BotCommands::Ceva(what) => {
+        let chat_id = message.update.chat_id();
+        let tokens: Vec<&str> = what.split_whitespace().collect();
+
+        let response = match tokens.as_slice() {
+        [] => {
+            match check_permission(&message, chat_id, Action::Dao).await {
+            Ok(_) => {
+                let mut statemap = STATEMAP.lock().unwrap();
+                let now = trapper::now();
+                let ceva = match statemap.get_mut(&chat_id) {
+                Some(trapper) => { trapper.ceva(now, &mut rand::thread_rng()) }
+                None => { Trapper::new().ceva(now, &mut rand::thread_rng()) }
+                };
+
+                ceva.unwrap_or_else(|| { "Nu am chef de nimic, ati oprit tot din /ceva".to_string() })
+            }
+            Err(x) => { x }
+            }
         }
-        _ => { 
-            let mut concatenated = tokens[1].clone();
-            for i in 2..tokens.len() {
-                concatenated = concatenated + &"~" + &tokens[i];
+        ["ponderi"] => {
+            let weights = chat_settings(chat_id).ceva;
+            let mut response = "Cat de des fac fiecare lucru la /ceva:\n".to_string();
+            for action in CEVA_ACTIONS {
+                response += &format!("{}: {}\n", action.name(), weights.get(*action));
             }
-            
+            response
+        }
+        [name, weight] => {
+            match (CevaAction::from_name(name), u32::from_str(weight)) {
+            (Some(action), Ok(weight)) => {
+                match check_permission(&message, chat_id, Action::ChangeSettings).await {
+                Ok(_) => {
+                    let mut statemap = STATEMAP.lock().unwrap();
+                    let trapper = statemap.entry(chat_id).or_insert_with(Trapper::new);
 
-            match ALIASES.lock().unwrap().get_by_left(&tokens[0]) {
-            Some(x) => { Ok((*x, concatenated)) }
-            None    => {
-                let result = i64::from_str(&tokens[0]);
-                match result {
-                Ok(x)  => { Ok((x, concatenated)) }
-                Err(x) => { Err(x.to_string()) }
+                    match trapper.settings.ceva.set(action, weight) {
+                    Ok(()) => {
+                        mark_dirty(chat_id);
+                        format!("Gata, {} are acum ponderea {}", action.name(), weight)
+                    }
+                    Err(x) => { x }
+                    }
+                }
+                Err(x) => { x }
                 }
             }
+            _ => { HELP_CEVA.to_string() }
             }
         }
+        _ => { HELP_CEVA.to_string() }
         };
 
-        let response = match result {
-        Ok((chat_id, gind)) => {
-            let mut statemap = STATEMAP.lock().unwrap();
-            let mut trapper = match statemap.remove(&chat_id) {
-            None => {
-                trapper::Trapper::new()
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Backup => {
+        let response = if !sent_by_admin(&message) {
+            ONLY_ADMIN.to_string()
+        } else {
+            // The same files the JSON storage writes, so they can be used as they are
+            let data = storage::to_versioned(&*STATEMAP.lock().unwrap());
+            let aliases = storage::to_versioned(&ALIASES.lock().unwrap().pairs());
+
+            let result = match (data, aliases) {
+            (Ok(data), Ok(aliases)) => {
+                match send_in_private(&message, "data2.JSON".to_string(), data).await {
+                Ok(()) => { send_in_private(&message, "aliases.JSON".to_string(), aliases).await }
+                Err(x) => { Err(x) }
+                }
             }
-            Some(x) => { x }
+            (Err(x), _) | (_, Err(x)) => { Err(x) }
             };
-        
-            trapper.thoughts.push(gind);
-            statemap.insert(chat_id, trapper);
-            "Am bagat un gind frumos".to_string()
-        }
-        Err(x) => {
-            x
-        }
+
+            match result { Ok(()) => { SENT_IN_PRIVATE.to_string() } Err(x) => { x } }
         };
 
         message.answer(response)
@@ -359,24 +1698,28 @@ async fn run_command(command: BotCommands, message: UpdateWithCx<AutoSend<Bot>,
             .log_on_error()
             .await;
     }
-    BotCommands::Gind => {
-        let chat_id = message.update.chat_id();
-        let response = {
-            let mut statemap = STATEMAP.lock().unwrap();
-            let mut trapper = match statemap.remove(&chat_id) {
-                None => { trapper::Trapper::new() }
-                Some(x) => { x }
+    BotCommands::Export(target) => {
+        let response = if !sent_by_admin(&message) {
+            ONLY_ADMIN.to_string()
+        } else {
+            let exported = find_chat(&target, message.update.chat_id()).and_then(|chat_id| {
+                match STATEMAP.lock().unwrap().get(&chat_id) {
+                Some(trapper) => { storage::export_trapper(trapper) }
+                None => { Err("Nu stiu nimic despre grupul asta".to_string()) }
+                }
+            });
+
+            let file_name = match target.trim() {
+            "" => { format!("{}.JSON", message.update.chat_id()) }
+            x => { format!("{}.JSON", x) }
             };
-        
-            trapper.shuffle_thoughts();
-            let gind = trapper.thoughts.pop();
-            statemap.insert(chat_id, trapper);
-            
-            match gind {
-            Some(x) => {x}
-            None => { "Nu gindesc, deci nu exist".to_string() }
-            }
-            
+
+            let result = match exported {
+            Ok(exported) => { send_in_private(&message, file_name, exported).await }
+            Err(x) => { Err(x) }
+            };
+
+            match result { Ok(()) => { SENT_IN_PRIVATE.to_string() } Err(x) => { x } }
         };
 
         message.answer(response)
@@ -384,43 +1727,109 @@ async fn run_command(command: BotCommands, message: UpdateWithCx<AutoSend<Bot>,
             .log_on_error()
             .await;
     }
-    BotCommands::Dao => {
-        let chat_id = message.update.chat_id();
-        let response = {
-            let mut statemap = STATEMAP.lock().unwrap();
-            let mut trapper = match statemap.remove(&chat_id) {
-                None => { trapper::Trapper::new() }
-                Some(x) => { x }
+    BotCommands::Import(target) => {
+        let response = if !sent_by_admin(&message) {
+            ONLY_ADMIN.to_string()
+        } else {
+            let imported = match find_chat(&target, message.update.chat_id()) {
+            Ok(chat_id) => {
+                download_reply(&message).await
+                    .and_then(|x| { storage::import_trapper(&x) })
+                    .map(|x| { (chat_id, x) })
+            }
+            Err(x) => { Err(x) }
             };
 
-            let response = trapper.markov.get_random();
-            statemap.insert(chat_id, trapper);
+            match imported {
+            Ok((chat_id, imported)) => {
+                let mut statemap = STATEMAP.lock().unwrap();
+                let trapper = statemap.entry(chat_id).or_insert_with(Trapper::new);
 
-            response
+                if trapper.markov.can_merge(&imported.markov) {
+                    let (commands, thoughts) = trapper.merge(chat_id, imported);
+                    mark_dirty(chat_id);
+                    format!("Am bagat {} comenzi noi, {} ginduri noi si tot ce stiam pentru /dao",
+                            commands, thoughts)
+                } else {
+                    "Ponderile de la /dao ar fi prea mari cu ce e deja pe grup, nu bag nimic".to_string()
+                }
+            }
+            Err(x) => { x }
+            }
         };
 
-        match response {
-        Some(x) => {
-            message.answer(x)
-                .await
-                .log_on_error()
-                .await;
-        }
-        None => {
-            message.answer("n-o dau da-te dreq")
-                .await
-                .log_on_error()
-                .await;
-        }
-        }
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
     }
-    _ => {
+    BotCommands::Stats | BotCommands::Groups => {
+        let response = if !sent_by_admin(&message) {
+            ONLY_ADMIN.to_string()
+        } else if matches!(command, BotCommands::Stats) {
+            bot_stats()
+        } else {
+            list_groups()
+        };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Broadcast(text) => {
+        let response = if !sent_by_admin(&message) {
+            ONLY_ADMIN.to_string()
+        } else if text.trim().is_empty() {
+            "Zi si ce sa zic: /broadcast [mesaj]".to_string()
+        } else {
+            match broadcast(&message.requester, text.trim()).await {
+            (sent, 0) => { format!("Am zis pe {} grupuri", sent) }
+            (sent, failed) => { format!("Am zis pe {} grupuri, pe {} n-am putut", sent, failed) }
+            }
+        };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    BotCommands::Reload | BotCommands::Save => {
+        let response = if !sent_by_admin(&message) {
+            ONLY_ADMIN.to_string()
+        } else if matches!(command, BotCommands::Reload) {
+            match tokio::task::spawn_blocking(reload_all).await {
+            Ok(Ok((groups, aliases))) => {
+                format!("Am citit iar {} grupuri si {} aliasuri", groups, aliases)
+            }
+            Ok(Err(x)) => { format!("N-am putut sa citesc, raman cu ce stiam: {}", x) }
+            Err(x) => { format!("N-am putut sa citesc: {}", x) }
+            }
+        } else {
+            match tokio::task::spawn_blocking(save_all).await {
+            Ok(Ok(())) => { "Am salvat tot".to_string() }
+            Ok(Err(x)) => { format!("N-am putut sa salvez: {}", x) }
+            Err(x) => { format!("N-am putut sa salvez: {}", x) }
+            }
+        };
+
+        message.answer(response)
+            .await
+            .log_on_error()
+            .await;
+    }
+    // Only gets here when the wizard isn't going
+    BotCommands::Anuleaza => {
+        message.answer(WIZARD_NOTHING)
+            .await
+            .log_on_error()
+            .await;
     }
     };
 }
 
 pub async fn process_message(message: UpdateWithCx<AutoSend<Bot>, Message>) {
-    let mut message_text = if let MessageKind::Common(ref message) = message.update.kind {
+    let message_text = if let MessageKind::Common(ref message) = message.update.kind {
         if let MediaKind::Text(ref message_text) = message.media_kind {
             message_text.text.clone()
         } else {
@@ -435,8 +1844,14 @@ pub async fn process_message(message: UpdateWithCx<AutoSend<Bot>, Message>) {
     if let Ok(command) = command {
         run_command(command, message).await;
     } else {
-        message_text.make_ascii_lowercase();
-        let words: Vec<&str> = message_text
+        let forwarded = message.update.forward_from().is_some() ||
+            message.update.forward_from_chat().is_some();
+        let from_bot = message.update.from().is_some_and(|x| { x.is_bot });
+        let sequence = TRAINING_FILTER.prepare(&message_text, forwarded, from_bot);
+
+        let mut lowercase_text = message_text.clone();
+        lowercase_text.make_ascii_lowercase();
+        let words: Vec<&str> = lowercase_text
             .split(|x: char| { !x.is_alphanumeric() })
             .collect();
         
@@ -450,51 +1865,265 @@ pub async fn process_message(message: UpdateWithCx<AutoSend<Bot>, Message>) {
             let trapper = statemap.remove(&message.update.chat_id());
 
             if let Some(mut trapper) = trapper {
-                trapper.shuffle_commands();
-                let response = if let Some(command) = trapper.commands.iter().find(|x| { x.eval(&words_map) } ) {
-                    command.response.clone()
+                let response = if !trapper.settings.triggers || trapper.is_muted(trapper::now()) {
+                    String::new()
                 } else {
-                    "".to_string()
+                    trapper.pick_response(&words_map, &mut rand::thread_rng()).unwrap_or_default()
                 };
 
-                trapper.markov.add_sequence(message_text);
+                if let Some(user) = message.update.from() {
+                    if let Some(ref username) = user.username {
+                        trapper.remember_username(username, user.id);
+                    }
+                }
+
+                if let Some(sequence) = sequence.filter(|_| { trapper.settings.learn }) {
+                    match message.update.from() {
+                    Some(user) => { trapper.add_sequence(user.id, sequence); }
+                    None => { trapper.markov.add_sequence(sequence); }
+                    }
+                    trapper.maintain_markov(&CHAIN_LIMITS, trapper::now());
+                    mark_dirty(message.update.chat_id());
+                }
 
+                let reply_mode = trapper.settings.reply_mode;
                 statemap.insert(message.update.chat_id(), trapper);
-                response
+                (response, reply_mode)
             } else {
-                "".to_string()
+                ("".to_string(), ReplyMode::default())
             }
         };
         
-        if response != "".to_string() {
+        match response {
+        (response, _) if response.is_empty() => {}
+        (response, ReplyMode::Message) => {
             message.answer(response)
                 .await
                 .log_on_error()
                 .await;
         }
+        (response, ReplyMode::Reply) => {
+            message.reply_to(response)
+                .await
+                .log_on_error()
+                .await;
+        }
+        }
+    }
+}
+
+// Trains the Markov chains of a group on an exported chat history, with the same filter
+// as the live messages. Returns how many messages were learned
+fn import_history(target: &str, path: &str) -> Result<usize, String> {
+    let chat_id = match target.trim() {
+    "" => { return Err(WRONG_ALIAS.to_string()); }
+    x => { find_chat(x, 0)? }
+    };
+
+    let learns = STATEMAP.lock().unwrap().get(&chat_id).is_none_or(|x| { x.settings.learn });
+    if !learns {
+        return Err("The group turned off the learning in /setari".to_string());
+    }
+
+    let messages = import::read_history(path)?;
+    log::info!("Read {} messages from {}", messages.len(), path);
+
+    let mut statemap = STATEMAP.lock().unwrap();
+    let trapper = statemap.entry(chat_id).or_insert_with(Trapper::new);
+    let mut trained = 0;
+
+    // The users who opted out with /nu_ma_invata are skipped, like on the chat
+    for message in messages {
+        let sequence = TRAINING_FILTER.prepare(&message.text, message.forwarded,
+                                               message.from_bot);
+        let learned = match (sequence, message.user_id) {
+        (Some(sequence), Some(user_id)) => { trapper.add_sequence(user_id, sequence) }
+        (Some(sequence), None) => {
+            trapper.markov.add_sequence(sequence);
+            true
+        }
+        (None, _) => { false }
+        };
+
+        if learned {
+            trained += 1;
+        }
     }
+    trapper.maintain_markov(&CHAIN_LIMITS, trapper::now());
+    mark_dirty(chat_id);
+
+    Ok(trained)
+}
+
+// Not async, so the flags are put in the environment before the runtime starts its threads
+fn main() {
+    let args = match config::take_flags(std::env::args().collect()) {
+    Ok((args, flags)) => {
+        // The flags win over the environment, everything else reads the settings from there
+        for (variable, value) in flags {
+            std::env::set_var(variable, value);
+        }
+        args
+    }
+    Err(x) => {
+        eprintln!("{}", x);
+        std::process::exit(1);
+    }
+    };
+
+    tokio::runtime::Runtime::new()
+        .expect("Failed to start the runtime")
+        .block_on(start(args));
 }
 
-#[tokio::main]
-async fn main() {
-    run().await;
+async fn start(args: Vec<String>) {
+    match args.get(1).map(|x| { x.as_str() }) {
+    Some("migreaza") => {
+        teloxide::enable_logging!();
+        let result = storage::sqlite_path().and_then(|path| {
+            migrate_to_sqlite(&path)?;
+            Ok(path)
+        });
+
+        match result {
+        Ok(path) => {
+            log::info!("Migrated everything to {}, start the bot with STORAGE=sqlite",
+                       path.display());
+        }
+        Err(x) => {
+            log::error!("Failed to migrate to SQLite: {}", x);
+            std::process::exit(1);
+        }
+        }
+    }
+    Some("importa") => {
+        teloxide::enable_logging!();
+        if args.len() != 4 {
+            log::error!("Usage: {} importa [alias or chat id] [file]", args[0]);
+            std::process::exit(1);
+        }
+
+        match import_history(&args[2], &args[3]) {
+        Ok(trained) => {
+            log::info!("Trained on {} messages", trained);
+            if save_all().is_err() {
+                std::process::exit(1);
+            }
+        }
+        Err(x) => {
+            log::error!("Failed to import {}: {}", args[3], x);
+            std::process::exit(1);
+        }
+        }
+    }
+    _ => {
+        run().await;
+    }
+    }
 }
 
 async fn run() {
     teloxide::enable_logging!();
     log::info!("Starting shared_state_bot...");
     
-    ctrlc::set_handler(exit_program).expect("Failed to set handler");
+    // Load everything now instead of on the first message, so problems show up right away
+    lazy_static::initialize(&ALIASES);
+    lazy_static::initialize(&STATEMAP);
+
+    ctrlc::set_handler(on_signal).expect("Failed to set handler");
+    tokio::spawn(autosave());
 
     let bot = Bot::from_env().auto_send();
-    
-    Dispatcher::new(bot)
-        .messages_handler(|rx: DispatcherHandlerRx<AutoSend<Bot>, Message>| {
+    let delivery = tokio::spawn(deliver_thoughts(bot.clone()));
+
+    // The listener ends when the shutdown is requested, which makes the dispatcher return.
+    // The handler ends once every update it already got is handled
+    let listener = update_listeners::polling_default(bot.clone())
+        .inspect(|update| {
+            if let Ok(update) = update {
+                LAST_UPDATE.store(update.id, Ordering::SeqCst);
+            }
+        })
+        .take_until(shutdown_requested());
+    let (messages_drained, wait_messages) = oneshot::channel();
+    let (callbacks_drained, wait_callbacks) = oneshot::channel();
+
+    // The private chats and the wizard's buttons go one at a time per chat, through the
+    // /adauga wizard
+    let (wizard_tx, wizard_rx) = mpsc::unbounded_channel();
+    tokio::spawn(DialogueDispatcher::new(wizard::handle).handle(wizard_rx));
+    let wizard_messages = wizard_tx.clone();
+
+    Dispatcher::new(bot.clone())
+        .messages_handler(|rx: DispatcherHandlerRx<AutoSend<Bot>, Message>| async move {
+            UnboundedReceiverStream::new(rx)
+                .for_each_concurrent(None, |message| {
+                    let wizard = wizard_messages.clone();
+                    async move {
+                        if !message.update.chat.is_private() {
+                            process_message(message).await;
+                            return;
+                        }
+
+                        let UpdateWithCx { requester, update } = message;
+                        update_started();
+                        if wizard.send(UpdateWithCx { requester, update: ChatUpdate::Message(update) }).is_err() {
+                            update_done();
+                        }
+                    }
+                })
+                .await;
+            messages_drained.send(()).ok();
+        })
+        .callback_queries_handler(|rx: DispatcherHandlerRx<AutoSend<Bot>, CallbackQuery>| async move {
             UnboundedReceiverStream::new(rx)
-                .for_each_concurrent(None, |message| async move {
-                    process_message(message).await;
+                .for_each_concurrent(None, |query| {
+                    let wizard = wizard_tx.clone();
+                    async move {
+                        let for_wizard = query.update.data.as_ref()
+                            .is_some_and(|x| { x.starts_with(wizard::CALLBACK) });
+                        if !for_wizard {
+                            process_callback(query).await;
+                            return;
+                        }
+
+                        let UpdateWithCx { requester, update } = query;
+                        update_started();
+                        if wizard.send(UpdateWithCx { requester, update: ChatUpdate::Button(update) }).is_err() {
+                            update_done();
+                        }
+                    }
                 })
+                .await;
+            callbacks_drained.send(()).ok();
         })
-        .dispatch()
+        .dispatch_with_listener(listener,
+                                LoggingErrorHandler::with_custom_text("An error from the update listener"))
         .await;
+
+    log::info!("Waiting for the updates in progress");
+    wait_messages.await.ok();
+    wait_callbacks.await.ok();
+    wait_updates().await;
+    delivery.await.ok();
+    confirm_updates(&bot).await;
+
+    match tokio::task::spawn_blocking(save_all).await {
+    Ok(Ok(())) => { log::info!("Bye"); }
+    Ok(Err(_)) => { log::error!("Stopped, but the last save failed"); }
+    Err(x) => { log::error!("Stopped, but the last save failed: {}", x); }
+    }
+}
+
+// Telegram only forgets the updates we got when we ask for the next ones. Without this,
+// the last updates before the shutdown would be handled again at the next start
+async fn confirm_updates(bot: &AutoSend<Bot>) {
+    let last = LAST_UPDATE.load(Ordering::SeqCst);
+    if last < 0 {
+        return;
+    }
+
+    if let Err(x) = bot.get_updates().offset(last + 1).limit(1).timeout(0).await {
+        log::warn!("Failed to confirm the last updates: {}", x);
+    }
 }
@@ -0,0 +1,352 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::aliases::Aliases;
+use crate::trapper::Trapper;
+
+pub mod json;
+pub mod migrations;
+pub mod sqlite;
+
+pub use self::migrations::FORMAT_VERSION;
+
+// Where the state of the bot lives between restarts. Implementations that can write
+// incrementally only need to care about the groups in `changed`, the others may just
+// rewrite everything
+pub trait Storage: Send {
+    fn load_aliases(&mut self) -> Result<Aliases, String>;
+    fn save_aliases(&mut self, aliases: &Aliases) -> Result<(), String>;
+    fn load_trappers(&mut self) -> Result<HashMap<i64, Trapper>, String>;
+    fn save_trappers(&mut self, statemap: &HashMap<i64, Trapper>,
+                     changed: &HashSet<i64>) -> Result<(), String>;
+
+    // Whether save_trappers only looks at the groups in `changed`, so it only needs those
+    fn incremental(&self) -> bool {
+        false
+    }
+}
+
+// Every file of the bot lives in DATA_DIR (the current directory by default). Bots that
+// share a host and a DATA_DIR get their own subdirectory with INSTANCE
+pub fn data_dir() -> Result<PathBuf, String> {
+    let mut dir = PathBuf::from(std::env::var("DATA_DIR").unwrap_or_else(|_| { ".".to_string() }));
+
+    if let Ok(instance) = std::env::var("INSTANCE") {
+        dir.push(check_instance(&instance)?);
+    }
+
+    fs::create_dir_all(&dir)
+        .map_err(|x| { format!("Failed to create the data directory {}: {}", dir.display(), x) })?;
+    Ok(dir)
+}
+
+// The instance is a single directory name, it can't point outside of DATA_DIR
+fn check_instance(instance: &str) -> Result<&str, String> {
+    let valid = !instance.is_empty() && instance != "." && instance != ".." &&
+        instance.chars().all(|x| { x.is_alphanumeric() || x == '_' || x == '-' || x == '.' });
+
+    if valid {
+        Ok(instance)
+    } else {
+        Err(format!("Invalid instance name: {}, use letters, digits, '_', '-' and '.'", instance))
+    }
+}
+
+// SQLITE_PATH overrides the location of the database, relative paths being taken from the
+// data directory
+pub fn sqlite_path() -> Result<PathBuf, String> {
+    let name = std::env::var("SQLITE_PATH").unwrap_or_else(|_| { "data.sqlite".to_string() });
+    Ok(data_dir()?.join(name))
+}
+
+pub fn json_storage() -> Result<json::JsonStorage, String> {
+    let dir = data_dir()?;
+    Ok(json::JsonStorage::new(&dir.join("data2.JSON"), &dir.join("aliases.JSON")))
+}
+
+// STORAGE=json (the default) keeps everything in data2.JSON and aliases.JSON,
+// STORAGE=sqlite keeps everything in the database at SQLITE_PATH (data.sqlite by default)
+pub fn from_env() -> Result<Box<dyn Storage>, String> {
+    let kind = std::env::var("STORAGE").unwrap_or_else(|_| { "json".to_string() });
+
+    match kind.trim().to_lowercase().as_str() {
+    "json" => {
+        Ok(Box::new(json_storage()?))
+    }
+    "sqlite" => {
+        Ok(Box::new(sqlite::SqliteStorage::open(&sqlite_path()?)?))
+    }
+    x => {
+        Err(format!("Unknown storage: {}, use json or sqlite", x))
+    }
+    }
+}
+
+// Copies the aliases and every group from one storage to another, returning how many of
+// each were copied
+pub fn copy_storage(from: &mut dyn Storage, to: &mut dyn Storage) -> Result<(usize, usize), String> {
+    let aliases = from.load_aliases()?;
+    to.save_aliases(&aliases)?;
+
+    let statemap = from.load_trappers()?;
+    to.save_trappers(&statemap, &statemap.keys().copied().collect())?;
+
+    Ok((aliases.count(), statemap.len()))
+}
+
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    with_extension(path, ".bak")
+}
+
+// Writes the data in a temporary file first and only then moves it over the old one, so
+// a crash in the middle of the write never leaves us with half a file. The previous
+// version of the file is kept next to it as a backup
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temporary = with_extension(path, ".tmp");
+
+    let mut file = File::create(&temporary)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+
+    // The backup is a second name for the previous file, which the rename below doesn't
+    // touch. Copying is only for filesystems without hard links
+    if path.exists() {
+        let backup = backup_path(path);
+        match fs::remove_file(&backup) {
+        Err(x) if x.kind() != io::ErrorKind::NotFound => { return Err(x); }
+        _ => {}
+        }
+        if fs::hard_link(path, &backup).is_err() {
+            fs::copy(path, &backup)?;
+        }
+    }
+    fs::rename(&temporary, path)?;
+
+    // The rename itself is only durable once the directory is synced. Not every platform
+    // lets us open a directory, so this is best effort
+    let directory = match path.parent() {
+    Some(x) if !x.as_os_str().is_empty() => { x }
+    _ => { Path::new(".") }
+    };
+    if let Ok(directory) = File::open(directory) {
+        directory.sync_all().ok();
+    }
+
+    Ok(())
+}
+
+// Wraps the data in a {"version": .., "data": ..} header
+pub fn to_versioned<T: Serialize>(data: &T) -> Result<String, String> {
+    let data = serde_json::to_value(data).map_err(|x| { x.to_string() })?;
+    Ok(json!({ "version": FORMAT_VERSION, "data": data }).to_string())
+}
+
+// Returns the version and the data. Data from before the versions were written doesn't
+// have a header and counts as version 0
+pub fn parse_versioned(content: &str) -> Result<(u64, Value), String> {
+    let mut value: Value = serde_json::from_str(content).map_err(|x| { x.to_string() })?;
+
+    let version = value.get("version").and_then(|x| { x.as_u64() });
+    match (version, value.get_mut("data")) {
+    (Some(version), Some(data)) => { Ok((version, data.take())) }
+    _ => { Ok((0, value)) }
+    }
+}
+
+pub fn write_versioned<T: Serialize>(path: &Path, data: &T) -> Result<(), String> {
+    let serialized = to_versioned(data)?;
+
+    write_atomic(path, serialized.as_bytes())
+        .map_err(|x| { format!("Failed to write {}: {}", path.display(), x) })
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReadError {
+    // We couldn't read the file, which says nothing about what's in it
+    Io(String),
+    // We can't make any sense of the file
+    Corrupt(String),
+    // The file is fine, but written by a newer version of the bot
+    Unsupported(String),
+}
+
+// Returns the version and the data of a file, or None if the file doesn't exist
+pub fn read_versioned(path: &Path) -> Result<Option<(u64, Value)>, ReadError> {
+    let content = match fs::read_to_string(path) {
+    Ok(x) => { x }
+    Err(x) if x.kind() == io::ErrorKind::NotFound => { return Ok(None); }
+    Err(x) => { return Err(ReadError::Io(format!("Failed to read {}: {}", path.display(), x))); }
+    };
+
+    parse_versioned(&content)
+        .map(Some)
+        .map_err(|x| { ReadError::Corrupt(format!("{} is not valid JSON: {}", path.display(), x)) })
+}
+
+// A group as sent by /export: the exported part of its Trapper, with the same header as
+// the data files, so older exports go through the same migrations
+pub fn export_trapper(trapper: &Trapper) -> Result<String, String> {
+    to_versioned(&trapper.export())
+}
+
+pub fn import_trapper(content: &str) -> Result<Trapper, String> {
+    let (version, data) = parse_versioned(content)
+        .map_err(|x| { format!("Nu e JSON bun: {}", x) })?;
+    let data = migrations::migrate_group(version, data)?;
+
+    let trapper: Trapper = serde_json::from_value(data)
+        .map_err(|x| { format!("Nu e un grup exportat: {}", x) })?;
+    trapper.markov.check_weights()?;
+    Ok(trapper)
+}
+
+// Moves a file we can't read out of the way, so the bot can start and a human can look
+// at it later. Returns where the file went
+pub fn quarantine(path: &Path) -> io::Result<PathBuf> {
+    let destination = with_extension(path, &format!(".corrupt-{}", crate::trapper::now()));
+    fs::rename(path, &destination)?;
+
+    Ok(destination)
+}
+
+// A directory for the tests that touch the disk, removed with everything in it once the
+// test is done, even if it failed
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("trapperu_{}_{}", name, std::process::id()));
+        fs::remove_dir_all(&path).ok();
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trapper::adauga::Expression;
+    use crate::trapper::gind::Thought;
+
+    #[test]
+    fn atomic_write_keeps_backup() {
+        let directory = TempDir::new("storage");
+        let path = directory.join("data2.JSON");
+
+        write_atomic(&path, b"vechi").unwrap();
+        assert!(!backup_path(&path).exists());
+
+        write_atomic(&path, b"nou").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "nou");
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "vechi");
+        assert!(!with_extension(&path, ".tmp").exists());
+    }
+
+    #[test]
+    fn instance_names() {
+        assert!(check_instance("trapperu-2").is_ok());
+        assert!(check_instance("").is_err());
+        assert!(check_instance("..").is_err());
+        assert!(check_instance("../altceva").is_err());
+        assert!(check_instance("a/b").is_err());
+    }
+
+    #[test]
+    fn versioned_files() {
+        let directory = TempDir::new("versioned");
+        let path = directory.join("aliases.JSON");
+
+        assert_eq!(read_versioned(&path), Ok(None));
+
+        fs::write(&path, "[[\"yeet\", 5]]").unwrap();
+        assert_eq!(read_versioned(&path), Ok(Some((0, json!([["yeet", 5]])))));
+
+        write_versioned(&path, &vec![("yeet".to_string(), 5)]).unwrap();
+        assert_eq!(read_versioned(&path), Ok(Some((FORMAT_VERSION, json!([["yeet", 5]])))));
+
+        fs::write(&path, "").unwrap();
+        assert!(read_versioned(&path).is_err());
+
+        let moved = quarantine(&path).unwrap();
+        assert!(!path.exists());
+        assert!(moved.exists());
+    }
+
+    #[test]
+    fn export_import() {
+        let mut trapper = Trapper::new();
+        trapper.commands.push(serde_json::from_str::<Expression>(
+            r#"{"added_by": 5, "expr": {"Variable": "ceva"}, "response": "altceva",
+                "group_id": -469444439}"#).unwrap());
+        trapper.thoughts.push(Thought::new("un gind".to_string(), 5, 1000, 0));
+        trapper.add_sequence(5, "ana are mere".to_string());
+        trapper.remember_username("ana", 5);
+
+        let exported = import_trapper(&export_trapper(&trapper).unwrap()).unwrap();
+        assert_eq!(exported.commands, trapper.commands);
+        assert_eq!(exported.thoughts, trapper.thoughts);
+        assert_eq!(exported.markov, trapper.markov);
+        assert!(exported.user_markov.is_empty());
+        assert!(exported.usernames.is_empty());
+
+        // Importing the same group twice only adds up the chains
+        let mut target = Trapper::new();
+        assert_eq!(target.merge(-7, exported.clone()), (1, 1));
+        assert_eq!(target.merge(-7, exported), (0, 0));
+        assert_eq!(target.commands[0].group_id, -7);
+        assert_eq!(target.markov.sentences(), 2);
+
+        // Exports from before the versions are migrated like the data files
+        let old = import_trapper(r#"{"commands": [], "thoughts": ["vechi"], "markov": {"chain": {}}}"#)
+            .unwrap();
+        assert_eq!(old.thoughts, vec![Thought::new("vechi".to_string(), 0, 0, 0)]);
+
+        assert!(import_trapper("nu e json").is_err());
+        assert!(import_trapper("[1, 2]").is_err());
+
+        // Weights that would break /dao
+        let chain = |edges: &str| -> String {
+            format!(r#"{{"version": {}, "data": {{"commands": [], "thoughts": [],
+                        "markov": {{"chain": {{"": {}}}}}}}}}"#, FORMAT_VERSION, edges)
+        };
+        assert!(import_trapper(&chain(r#"[["a", 1]]"#)).is_ok());
+        assert!(import_trapper(&chain(r#"[["a", 0]]"#)).is_err());
+        assert!(import_trapper(&chain(r#"[["a", 2], ["b", -1]]"#)).is_err());
+        assert!(import_trapper(&chain(&format!(r#"[["a", {}], ["b", 1]]"#, i64::MAX))).is_err());
+
+        // Nor can a valid chain overflow the one it's merged into
+        let big = import_trapper(&chain(&format!(r#"[["a", {}]]"#, i64::MAX))).unwrap();
+        assert!(Trapper::new().markov.can_merge(&big.markov));
+        assert!(!trapper.markov.can_merge(&big.markov));
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::aliases::Aliases;
+use crate::storage::{backup_path, quarantine, read_versioned, write_versioned, ReadError, Storage,
+                     FORMAT_VERSION};
+use crate::storage::migrations::{migrate_aliases, migrate_statemap};
+use crate::trapper::Trapper;
+
+// Brings the data of a file from its version to the current one
+type Migrate = fn(u64, Value) -> Result<Value, String>;
+
+// The original storage: every save rewrites both files completely
+pub struct JsonStorage {
+    data_path: PathBuf,
+    aliases_path: PathBuf,
+}
+
+// Reads one file, Ok(None) meaning that it doesn't exist
+fn read_file<T: DeserializeOwned>(path: &Path, migrate: Migrate) -> Result<Option<T>, ReadError> {
+    let (version, data) = match read_versioned(path)? {
+    Some(x) => { x }
+    None => { return Ok(None); }
+    };
+
+    if version > FORMAT_VERSION {
+        return Err(ReadError::Unsupported(
+            format!("{} was written by a newer version of the bot ({} > {})",
+                    path.display(), version, FORMAT_VERSION)));
+    }
+
+    let data = migrate(version, data)
+        .map_err(|x| { ReadError::Corrupt(format!("Failed to migrate {} from version {}: {}",
+                                                  path.display(), version, x)) })?;
+
+    serde_json::from_value(data)
+        .map(Some)
+        .map_err(|x| { ReadError::Corrupt(format!("Failed deserializing {}: {}",
+                                                  path.display(), x)) })
+}
+
+// Reads a file, falling back to its backup if it's missing or corrupt and to an empty
+// state if that doesn't work either. Corrupt files are moved out of the way, files we can't
+// read or from newer versions are an error so we don't overwrite them
+fn load_or_recover<T: DeserializeOwned + Default>(path: &Path,
+                                                  migrate: Migrate) -> Result<T, String> {
+    let backup = backup_path(path);
+
+    for candidate in &[path, backup.as_path()] {
+        match read_file(candidate, migrate) {
+        Ok(Some(x)) => {
+            if *candidate != path {
+                log::warn!("Recovered the data from the backup {}", candidate.display());
+            }
+            return Ok(x);
+        }
+        Ok(None) => {
+            log::info!("{} doesn't exist", candidate.display());
+        }
+        Err(ReadError::Io(x)) | Err(ReadError::Unsupported(x)) => {
+            return Err(x);
+        }
+        Err(ReadError::Corrupt(x)) => {
+            log::error!("{}", x);
+            match quarantine(candidate) {
+            Ok(destination) => {
+                log::warn!("Moved {} to {}", candidate.display(), destination.display());
+            }
+            Err(x) => {
+                log::error!("Failed to move {} out of the way: {}", candidate.display(), x);
+            }
+            }
+        }
+        }
+    }
+
+    log::warn!("Nothing to recover for {}, starting from scratch", path.display());
+    Ok(T::default())
+}
+
+impl JsonStorage {
+    pub fn new(data_path: &Path, aliases_path: &Path) -> JsonStorage {
+        JsonStorage {
+            data_path: data_path.to_path_buf(),
+            aliases_path: aliases_path.to_path_buf(),
+        }
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load_aliases(&mut self) -> Result<Aliases, String> {
+        let all_aliases: Vec<(String, i64)> = load_or_recover(&self.aliases_path, migrate_aliases)?;
+
+        Ok(Aliases::from_pairs(all_aliases))
+    }
+
+    fn save_aliases(&mut self, aliases: &Aliases) -> Result<(), String> {
+        write_versioned(&self.aliases_path, &aliases.pairs())
+    }
+
+    fn load_trappers(&mut self) -> Result<HashMap<i64, Trapper>, String> {
+        load_or_recover(&self.data_path, migrate_statemap)
+    }
+
+    fn save_trappers(&mut self, statemap: &HashMap<i64, Trapper>,
+                     _changed: &HashSet<i64>) -> Result<(), String> {
+        write_versioned(&self.data_path, statemap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::storage::TempDir;
+
+    #[test]
+    fn recovery() {
+        let directory = TempDir::new("json");
+        let data_path = directory.join("data2.JSON");
+        let aliases_path = directory.join("aliases.JSON");
+        let mut storage = JsonStorage::new(&data_path, &aliases_path);
+
+        // First run, nothing exists yet
+        assert!(storage.load_aliases().unwrap().count() == 0);
+        assert!(storage.load_trappers().unwrap().is_empty());
+
+        // Files from before the version header
+        fs::write(&aliases_path, "[[\"yeet\", -5]]").unwrap();
+        assert_eq!(storage.load_aliases().unwrap().get("yeet"), Some(-5));
+
+        // A good save followed by a corrupt one
+        let mut statemap = HashMap::new();
+        statemap.insert(-5, Trapper::new());
+        storage.save_trappers(&statemap, &HashSet::new()).unwrap();
+        storage.save_trappers(&statemap, &HashSet::new()).unwrap();
+        fs::write(&data_path, "{\"-5\": {\"comm").unwrap();
+
+        assert_eq!(storage.load_trappers().unwrap().len(), 1);
+        assert!(!data_path.exists());
+        assert_eq!(fs::read_dir(directory.path()).unwrap()
+                       .filter(|x| { x.as_ref().unwrap().file_name().to_str().unwrap()
+                                          .starts_with("data2.JSON.corrupt-") })
+                       .count(), 1);
+
+        // The backup is corrupt too
+        fs::write(backup_path(&data_path), "").unwrap();
+        assert!(storage.load_trappers().unwrap().is_empty());
+
+        // Files from the future are left alone
+        fs::write(&aliases_path, "{\"version\": 1000, \"data\": []}").unwrap();
+        assert!(storage.load_aliases().is_err());
+        assert!(aliases_path.exists());
+
+        // Neither are files we can't read. A directory can't be read even by root, unlike a
+        // file without permissions
+        fs::remove_file(&data_path).ok();
+        fs::create_dir(&data_path).unwrap();
+        assert!(storage.load_trappers().is_err());
+        assert!(data_path.is_dir());
+    }
+}
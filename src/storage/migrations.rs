@@ -0,0 +1,189 @@
+use serde_json::{json, Map, Value};
+
+// Turns data saved by one version of the bot into data of the next version
+type Step = fn(Value) -> Result<Value, String>;
+
+struct Migration {
+    // Applied to every group (a serialized Trapper)
+    group: Step,
+    // Applied to the list of aliases
+    aliases: Step,
+}
+
+// MIGRATIONS[i] takes data from version i to version i + 1, so the current version is the
+// number of migrations. Never change a migration once it was released, add a new one
+const MIGRATIONS: &[Migration] = &[
+    Migration { group: group_v0_to_v1, aliases: unchanged },
+    Migration { group: group_v1_to_v2, aliases: unchanged },
+];
+
+pub const FORMAT_VERSION: u64 = MIGRATIONS.len() as u64;
+
+fn unchanged(data: Value) -> Result<Value, String> {
+    Ok(data)
+}
+
+fn as_object(data: &mut Value) -> Result<&mut Map<String, Value>, String> {
+    match data {
+    Value::Object(x) => { Ok(x) }
+    x => { Err(format!("Expected an object, found {}", x)) }
+    }
+}
+
+// Version 0 chains didn't know when they decayed or how many messages they learned. The
+// messages are estimated from the edges leaving the start of the messages
+fn markov_v0_to_v1(markov: &mut Value) -> Result<(), String> {
+    let markov = as_object(markov)?;
+
+    let sentences: i64 = markov.get("chain")
+        .and_then(|x| { x.get("") })
+        .and_then(|x| { x.as_array() })
+        .map(|edges| {
+            edges.iter().filter_map(|x| { x.get(1).and_then(|x| { x.as_i64() }) }).sum()
+        })
+        .unwrap_or(0);
+
+    markov.entry("chain").or_insert_with(|| { json!({}) });
+    markov.entry("last_decay").or_insert_with(|| { json!(0) });
+    markov.entry("sentences").or_insert_with(|| { json!(sentences) });
+
+    Ok(())
+}
+
+// Version 0 is everything from before the files had a version: groups may miss the
+// per-user chains and everything that came with them
+fn group_v0_to_v1(mut group: Value) -> Result<Value, String> {
+    let fields = as_object(&mut group)?;
+
+    fields.entry("commands").or_insert_with(|| { json!([]) });
+    fields.entry("thoughts").or_insert_with(|| { json!([]) });
+    fields.entry("markov").or_insert_with(|| { json!({}) });
+    fields.entry("user_markov").or_insert_with(|| { json!({}) });
+    fields.entry("usernames").or_insert_with(|| { json!({}) });
+    fields.entry("no_imitation").or_insert_with(|| { json!([]) });
+
+    markov_v0_to_v1(fields.get_mut("markov").unwrap())?;
+    for markov in as_object(fields.get_mut("user_markov").unwrap())?.values_mut() {
+        markov_v0_to_v1(markov)?;
+    }
+
+    Ok(group)
+}
+
+// Version 1 thoughts were only the text. We don't know who added them or when, so they
+// get 0 for both and never expire
+fn group_v1_to_v2(mut group: Value) -> Result<Value, String> {
+    let fields = as_object(&mut group)?;
+
+    if let Some(Value::Array(thoughts)) = fields.get_mut("thoughts") {
+        for thought in thoughts.iter_mut() {
+            if let Value::String(text) = thought {
+                *thought = json!({ "text": text, "added_by": 0, "added_at": 0, "expires_at": null });
+            }
+        }
+    }
+
+    Ok(group)
+}
+
+fn check_version(version: u64) -> Result<(), String> {
+    if version > FORMAT_VERSION {
+        Err(format!("The data was written by a newer version of the bot ({} > {})",
+                    version, FORMAT_VERSION))
+    } else {
+        Ok(())
+    }
+}
+
+// Brings one group from the given version to the current one
+pub fn migrate_group(version: u64, mut group: Value) -> Result<Value, String> {
+    check_version(version)?;
+
+    for migration in &MIGRATIONS[version as usize..] {
+        group = (migration.group)(group)?;
+    }
+
+    Ok(group)
+}
+
+// Brings a map from chat ids to groups from the given version to the current one
+pub fn migrate_statemap(version: u64, mut statemap: Value) -> Result<Value, String> {
+    check_version(version)?;
+
+    for group in as_object(&mut statemap)?.values_mut() {
+        *group = migrate_group(version, group.take())?;
+    }
+
+    Ok(statemap)
+}
+
+pub fn migrate_aliases(version: u64, mut aliases: Value) -> Result<Value, String> {
+    check_version(version)?;
+
+    for migration in &MIGRATIONS[version as usize..] {
+        aliases = (migration.aliases)(aliases)?;
+    }
+
+    Ok(aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::trapper::Trapper;
+    use crate::trapper::gind::Thought;
+
+    // Files exactly as the bots of each version wrote them
+    const DATA_V0: &str = include_str!("fixtures/data2_v0.JSON");
+    const DATA_V1: &str = include_str!("fixtures/data2_v1.JSON");
+    const ALIASES_V0: &str = include_str!("fixtures/aliases_v0.JSON");
+
+    fn load_statemap(version: u64, content: &str) -> HashMap<i64, Trapper> {
+        let data = serde_json::from_str(content).unwrap();
+        serde_json::from_value(migrate_statemap(version, data).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn data_v0() {
+        let mut statemap = load_statemap(0, DATA_V0);
+        let trapper = statemap.get_mut(&-469444439).unwrap();
+
+        assert_eq!(trapper.commands.len(), 2);
+        assert_eq!(trapper.thoughts, vec![Thought::new("sa moara familia mea".to_string(), 0, 0, 0)]);
+        assert_eq!(trapper.markov.sentences(), 3);
+        assert!(trapper.user_markov.is_empty());
+        assert_eq!(trapper.markov.get_random().map(|x| { x.starts_with("ba") }), Some(true));
+
+        // Migrating doesn't lose anything from the current version
+        let current = serde_json::to_value(&statemap).unwrap();
+        assert_eq!(migrate_statemap(FORMAT_VERSION, current.clone()), Ok(current));
+    }
+
+    #[test]
+    fn data_v1() {
+        let statemap = load_statemap(1, DATA_V1);
+        let trapper = &statemap[&-469444439];
+
+        assert_eq!(trapper.thoughts.iter().map(|x| { x.text.as_str() }).collect::<Vec<_>>(),
+                   vec!["un gind", "inca unul"]);
+        assert!(trapper.thoughts.iter().all(|x| { x.added_by == 0 && x.expires_at.is_none() }));
+        assert_eq!(trapper.user_markov[&1256262].sentences(), 1);
+        assert!(trapper.no_imitation.contains(&42));
+    }
+
+    #[test]
+    fn aliases_v0() {
+        let aliases = migrate_aliases(0, serde_json::from_str(ALIASES_V0).unwrap()).unwrap();
+        let aliases: Vec<(String, i64)> = serde_json::from_value(aliases).unwrap();
+
+        assert_eq!(aliases, vec![("yeet".to_string(), -469444439),
+                                 ("aztecii".to_string(), -1001234567890)]);
+    }
+
+    #[test]
+    fn future_versions() {
+        assert!(migrate_group(FORMAT_VERSION + 1, json!({})).is_err());
+        assert!(migrate_aliases(FORMAT_VERSION + 1, json!([])).is_err());
+    }
+}
@@ -0,0 +1,594 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, Transaction, NO_PARAMS};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::aliases::Aliases;
+use crate::storage::{quarantine, Storage, FORMAT_VERSION};
+use crate::storage::migrations::migrate_group;
+use crate::trapper::Trapper;
+use crate::trapper::dao::{Markov, Revision};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS aliases (
+    alias TEXT PRIMARY KEY,
+    chat_id INTEGER NOT NULL
+);
+-- Everything from the Trapper that doesn't have its own table, as JSON
+CREATE TABLE IF NOT EXISTS groups (
+    chat_id INTEGER PRIMARY KEY,
+    data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS triggers (
+    chat_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (chat_id, position)
+);
+CREATE TABLE IF NOT EXISTS thoughts (
+    chat_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (chat_id, position)
+);
+-- user_id is 0 for the chain of the whole group, data has everything but the edges
+CREATE TABLE IF NOT EXISTS markov_chains (
+    chat_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (chat_id, user_id)
+);
+CREATE TABLE IF NOT EXISTS markov_edges (
+    chat_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    source TEXT NOT NULL,
+    target TEXT NOT NULL,
+    weight INTEGER NOT NULL,
+    PRIMARY KEY (chat_id, user_id, source, target)
+);
+";
+
+// These fields of the Trapper are kept in their own tables
+const SPLIT_FIELDS: [&str; 4] = ["commands", "thoughts", "markov", "user_markov"];
+
+const GROUP_CHAIN: i64 = 0;
+
+// Keeps every group in its own rows, so a save only rewrites the groups that changed
+pub struct SqliteStorage {
+    connection: Connection,
+    // The revision of every chain in the database, by chat and user id. The edges of the
+    // chains that still have it aren't looked at when saving
+    revisions: HashMap<(i64, i64), Revision>,
+}
+
+fn sql_error(error: rusqlite::Error) -> String {
+    format!("SQLite error: {}", error)
+}
+
+fn json_error(error: serde_json::Error) -> String {
+    format!("Failed to (de)serialize a row: {}", error)
+}
+
+// Everything but the edges of a chain
+fn markov_data(markov: &Markov) -> Result<String, String> {
+    let mut data = serde_json::to_value(markov.without_edges()).map_err(json_error)?;
+    if let Some(data) = data.as_object_mut() {
+        data.remove("chain");
+    }
+
+    Ok(data.to_string())
+}
+
+// Makes the rows of a group in a (chat_id, key, data) table match `rows`, only writing
+// the ones that are new or different and deleting the ones that are gone
+fn sync_rows(transaction: &Transaction, table: &str, key: &str, chat_id: i64,
+             rows: Vec<(i64, String)>) -> Result<(), String> {
+    let mut old: HashMap<i64, String> = {
+        let mut statement = transaction.prepare_cached(
+            &format!("SELECT {}, data FROM {} WHERE chat_id = ?1", key, table))
+            .map_err(sql_error)?;
+        let rows = statement.query_map(params![chat_id], |row| { Ok((row.get(0)?, row.get(1)?)) })
+            .map_err(sql_error)?;
+        rows.collect::<Result<_, _>>().map_err(sql_error)?
+    };
+
+    let mut upsert = transaction.prepare_cached(
+        &format!("INSERT INTO {} (chat_id, {}, data) VALUES (?1, ?2, ?3)
+                  ON CONFLICT (chat_id, {}) DO UPDATE SET data = excluded.data", table, key, key))
+        .map_err(sql_error)?;
+    for (row_key, data) in rows {
+        if old.remove(&row_key).as_ref() != Some(&data) {
+            upsert.execute(params![chat_id, row_key, data]).map_err(sql_error)?;
+        }
+    }
+
+    let mut delete = transaction.prepare_cached(
+        &format!("DELETE FROM {} WHERE chat_id = ?1 AND {} = ?2", table, key))
+        .map_err(sql_error)?;
+    for row_key in old.keys() {
+        delete.execute(params![chat_id, row_key]).map_err(sql_error)?;
+    }
+
+    Ok(())
+}
+
+// Same as sync_rows, for the edges of a chain. The edges are updated in place, so they
+// load in the same order as they are in the chain
+fn sync_edges(transaction: &Transaction, chat_id: i64, user_id: i64,
+              markov: &Markov) -> Result<(), String> {
+    let mut old: HashMap<(String, String), i64> = {
+        let mut statement = transaction.prepare_cached(
+            "SELECT source, target, weight FROM markov_edges WHERE chat_id = ?1 AND user_id = ?2")
+            .map_err(sql_error)?;
+        let rows = statement.query_map(params![chat_id, user_id], |row| {
+            Ok(((row.get(0)?, row.get(1)?), row.get(2)?))
+        }).map_err(sql_error)?;
+        rows.collect::<Result<_, _>>().map_err(sql_error)?
+    };
+
+    let mut upsert = transaction.prepare_cached(
+        "INSERT INTO markov_edges (chat_id, user_id, source, target, weight)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (chat_id, user_id, source, target) DO UPDATE SET weight = excluded.weight")
+        .map_err(sql_error)?;
+    for (from, to, weight) in markov.edges() {
+        if old.remove(&(from.clone(), to.clone())) != Some(weight) {
+            upsert.execute(params![chat_id, user_id, from, to, weight]).map_err(sql_error)?;
+        }
+    }
+
+    let mut delete = transaction.prepare_cached(
+        "DELETE FROM markov_edges WHERE chat_id = ?1 AND user_id = ?2 AND source = ?3 AND target = ?4")
+        .map_err(sql_error)?;
+    for (from, to) in old.keys() {
+        delete.execute(params![chat_id, user_id, from, to]).map_err(sql_error)?;
+    }
+
+    Ok(())
+}
+
+// The rows of a list kept in a (chat_id, position, data) table
+fn by_position<T: Serialize>(list: &[T]) -> Result<Vec<(i64, String)>, String> {
+    list.iter().enumerate()
+        .map(|(position, x)| { Ok((position as i64, serde_json::to_string(x).map_err(json_error)?)) })
+        .collect()
+}
+
+// The edges of the group chain (user_id 0) or of the chain of a user
+fn chain_of(group: &mut Map<String, Value>, user_id: i64) -> Option<&mut Map<String, Value>> {
+    let markov = if user_id == GROUP_CHAIN {
+        group.get_mut("markov")?
+    } else {
+        group.get_mut("user_markov")?.get_mut(user_id.to_string())?
+    };
+
+    markov.get_mut("chain")?.as_object_mut()
+}
+
+// Creates the tables that are missing. A database without any table gets the current
+// version, since everything written to it will be in the current format
+fn create_schema(connection: &Connection) -> rusqlite::Result<()> {
+    let tables: i64 = connection.query_row("SELECT count(*) FROM sqlite_master", NO_PARAMS,
+                                           |row| { row.get(0) })?;
+
+    connection.execute_batch(SCHEMA)?;
+    if tables == 0 {
+        connection.pragma_update(None, "user_version", &(FORMAT_VERSION as i64))?;
+    }
+
+    Ok(())
+}
+
+impl SqliteStorage {
+    // A file that isn't a database is moved out of the way and we start with an empty one
+    pub fn open(path: &Path) -> Result<SqliteStorage, String> {
+        let connection = Connection::open(path)
+            .map_err(|x| { format!("Failed to open {}: {}", path.display(), x) })?;
+
+        let connection = match create_schema(&connection) {
+        Ok(()) => { connection }
+        Err(rusqlite::Error::SqliteFailure(error, _))
+            if error.code == ErrorCode::NotADatabase || error.code == ErrorCode::DatabaseCorrupt => {
+            log::error!("{} is corrupt: {}", path.display(), error);
+            drop(connection);
+
+            let destination = quarantine(path)
+                .map_err(|x| { format!("Failed to move {} out of the way: {}", path.display(), x) })?;
+            log::warn!("Moved {} to {}, starting from scratch", path.display(), destination.display());
+
+            let connection = Connection::open(path)
+                .map_err(|x| { format!("Failed to open {}: {}", path.display(), x) })?;
+            create_schema(&connection).map_err(sql_error)?;
+            connection
+        }
+        Err(x) => { return Err(sql_error(x)); }
+        };
+
+        Ok(SqliteStorage {
+            connection,
+            revisions: HashMap::new(),
+        })
+    }
+
+    fn delete_trapper(transaction: &Transaction, chat_id: i64) -> Result<(), String> {
+        for table in &["groups", "triggers", "thoughts", "markov_chains", "markov_edges"] {
+            transaction.execute(&format!("DELETE FROM {} WHERE chat_id = ?1", table),
+                                params![chat_id])
+                .map_err(sql_error)?;
+        }
+
+        Ok(())
+    }
+
+    // Brings the rows of a group up to date, writing only what changed since the last save.
+    // The edges are only compared for the chains whose revision isn't in `saved`. Returns
+    // the revisions of the chains as they are now in the database
+    fn write_trapper(transaction: &Transaction, chat_id: i64, trapper: &Trapper,
+                     saved: &HashMap<(i64, i64), Revision>) -> Result<Vec<(i64, Revision)>, String> {
+        let mut data = serde_json::to_value(trapper.group_data()).map_err(json_error)?;
+        match data.as_object_mut() {
+        Some(data) => {
+            for field in &SPLIT_FIELDS {
+                data.remove(*field);
+            }
+        }
+        None => { return Err("A group isn't serialized as an object".to_string()); }
+        }
+
+        let data = data.to_string();
+        let old: Option<String> = transaction
+            .query_row("SELECT data FROM groups WHERE chat_id = ?1", params![chat_id],
+                       |row| { row.get(0) })
+            .optional()
+            .map_err(sql_error)?;
+        if old.as_ref() != Some(&data) {
+            transaction.execute("INSERT INTO groups (chat_id, data) VALUES (?1, ?2)
+                                 ON CONFLICT (chat_id) DO UPDATE SET data = excluded.data",
+                                params![chat_id, data])
+                .map_err(sql_error)?;
+        }
+
+        sync_rows(transaction, "triggers", "position", chat_id, by_position(&trapper.commands)?)?;
+        sync_rows(transaction, "thoughts", "position", chat_id, by_position(&trapper.thoughts)?)?;
+
+        let chains: Vec<(i64, &Markov)> = std::iter::once((GROUP_CHAIN, &trapper.markov))
+            .chain(trapper.user_markov.iter().map(|(user_id, markov)| { (*user_id, markov) }))
+            .collect();
+
+        let mut rows = vec![];
+        for (user_id, markov) in &chains {
+            rows.push((*user_id, markov_data(markov)?));
+        }
+        sync_rows(transaction, "markov_chains", "user_id", chat_id, rows)?;
+        transaction.execute("DELETE FROM markov_edges WHERE chat_id = ?1 AND user_id NOT IN
+                             (SELECT user_id FROM markov_chains WHERE chat_id = ?1)",
+                            params![chat_id])
+            .map_err(sql_error)?;
+
+        let mut revisions = vec![];
+        for (user_id, markov) in chains {
+            if saved.get(&(chat_id, user_id)) != Some(&markov.revision()) {
+                sync_edges(transaction, chat_id, user_id, markov)?;
+            }
+            revisions.push((user_id, markov.revision()));
+        }
+
+        Ok(revisions)
+    }
+
+    // Forgets the revisions of a group and keeps the ones it was just saved with
+    fn remember_revisions(&mut self, chat_id: i64, revisions: Vec<(i64, Revision)>) {
+        self.revisions.retain(|key, _| { key.0 != chat_id });
+        self.revisions.extend(revisions.into_iter()
+            .map(|(user_id, revision)| { ((chat_id, user_id), revision) }));
+    }
+
+    // The version of the rows is kept in the user_version of the database. Databases from
+    // before the versions have 0, just like the JSON files without a header
+    fn version(&self) -> Result<u64, String> {
+        let version: i64 = self.connection
+            .query_row("PRAGMA user_version", NO_PARAMS, |row| { row.get(0) })
+            .map_err(sql_error)?;
+
+        Ok(version as u64)
+    }
+
+    fn query_rows(&self, sql: &str) -> Result<Vec<(i64, i64, String)>, String> {
+        let mut statement = self.connection.prepare(sql).map_err(sql_error)?;
+        let rows = statement.query_map(NO_PARAMS, |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        }).map_err(sql_error)?;
+
+        rows.collect::<Result<Vec<_>, _>>().map_err(sql_error)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load_aliases(&mut self) -> Result<Aliases, String> {
+        let mut statement = self.connection.prepare("SELECT alias, chat_id FROM aliases")
+            .map_err(sql_error)?;
+        let rows = statement.query_map(NO_PARAMS, |row| { Ok((row.get(0)?, row.get(1)?)) })
+            .map_err(sql_error)?;
+
+        let pairs: Vec<(String, i64)> = rows.collect::<Result<_, _>>().map_err(sql_error)?;
+
+        Ok(Aliases::from_pairs(pairs))
+    }
+
+    fn save_aliases(&mut self, aliases: &Aliases) -> Result<(), String> {
+        let transaction = self.connection.transaction().map_err(sql_error)?;
+
+        transaction.execute("DELETE FROM aliases", NO_PARAMS).map_err(sql_error)?;
+        for (alias, chat_id) in aliases.pairs() {
+            transaction.execute("INSERT INTO aliases (alias, chat_id) VALUES (?1, ?2)",
+                                params![alias, chat_id])
+                .map_err(sql_error)?;
+        }
+
+        transaction.commit().map_err(sql_error)
+    }
+
+    fn load_trappers(&mut self) -> Result<HashMap<i64, Trapper>, String> {
+        let mut groups: HashMap<i64, Map<String, Value>> = HashMap::new();
+
+        for (chat_id, _, data) in self.query_rows("SELECT chat_id, 0, data FROM groups")? {
+            let mut data: Map<String, Value> = serde_json::from_str(&data).map_err(json_error)?;
+            data.insert("commands".to_string(), Value::Array(vec![]));
+            data.insert("thoughts".to_string(), Value::Array(vec![]));
+            data.insert("user_markov".to_string(), Value::Object(Map::new()));
+            groups.insert(chat_id, data);
+        }
+
+        for (table, field) in &[("triggers", "commands"), ("thoughts", "thoughts")] {
+            let sql = format!("SELECT chat_id, position, data FROM {} ORDER BY chat_id, position",
+                              table);
+            for (chat_id, _, data) in self.query_rows(&sql)? {
+                let row: Value = serde_json::from_str(&data).map_err(json_error)?;
+                if let Some(Value::Array(rows)) = groups.get_mut(&chat_id)
+                                                        .and_then(|x| { x.get_mut(*field) }) {
+                    rows.push(row);
+                }
+            }
+        }
+
+        for (chat_id, user_id, data) in
+            self.query_rows("SELECT chat_id, user_id, data FROM markov_chains")? {
+            let mut data: Map<String, Value> = serde_json::from_str(&data).map_err(json_error)?;
+            data.insert("chain".to_string(), Value::Object(Map::new()));
+
+            let group = match groups.get_mut(&chat_id) { Some(x) => { x } None => { continue; } };
+            if user_id == GROUP_CHAIN {
+                group.insert("markov".to_string(), Value::Object(data));
+            } else if let Some(Value::Object(chains)) = group.get_mut("user_markov") {
+                chains.insert(user_id.to_string(), Value::Object(data));
+            }
+        }
+
+        let mut statement = self.connection.prepare(
+            "SELECT chat_id, user_id, source, target, weight FROM markov_edges")
+            .map_err(sql_error)?;
+        let edges = statement.query_map(NO_PARAMS, |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+        }).map_err(sql_error)?;
+
+        for edge in edges {
+            let (chat_id, user_id, from, to, weight): (i64, i64, String, String, i64) =
+                edge.map_err(sql_error)?;
+            let chain = groups.get_mut(&chat_id).and_then(|x| { chain_of(x, user_id) });
+
+            if let Some(Value::Array(edges)) = chain.map(|x| { x.entry(from).or_insert_with(|| { json!([]) }) }) {
+                edges.push(json!([to, weight]));
+            }
+        }
+        drop(statement);
+
+        self.revisions.clear();
+        let version = self.version()?;
+        let mut statemap: HashMap<i64, Trapper> = HashMap::new();
+        for (chat_id, data) in groups {
+            let data = migrate_group(version, Value::Object(data))?;
+            let trapper = serde_json::from_value(data).map_err(json_error)?;
+            statemap.insert(chat_id, trapper);
+        }
+
+        // The migrated groups are written back right away, so the rows never mix versions
+        if version < FORMAT_VERSION {
+            log::info!("Migrating the database from version {} to {}", version, FORMAT_VERSION);
+            let transaction = self.connection.transaction().map_err(sql_error)?;
+
+            for (chat_id, trapper) in &statemap {
+                SqliteStorage::delete_trapper(&transaction, *chat_id)?;
+                SqliteStorage::write_trapper(&transaction, *chat_id, trapper, &HashMap::new())?;
+            }
+            transaction.pragma_update(None, "user_version", &(FORMAT_VERSION as i64))
+                .map_err(sql_error)?;
+
+            transaction.commit().map_err(sql_error)?;
+        }
+
+        for (chat_id, trapper) in &statemap {
+            let revisions = std::iter::once((GROUP_CHAIN, trapper.markov.revision()))
+                .chain(trapper.user_markov.iter().map(|(user_id, x)| { (*user_id, x.revision()) }))
+                .collect();
+            self.remember_revisions(*chat_id, revisions);
+        }
+
+        Ok(statemap)
+    }
+
+    fn save_trappers(&mut self, statemap: &HashMap<i64, Trapper>,
+                     changed: &HashSet<i64>) -> Result<(), String> {
+        let transaction = self.connection.transaction().map_err(sql_error)?;
+
+        let mut written = vec![];
+        for chat_id in changed {
+            let revisions = match statemap.get(chat_id) {
+            Some(trapper) => {
+                SqliteStorage::write_trapper(&transaction, *chat_id, trapper, &self.revisions)?
+            }
+            None => {
+                SqliteStorage::delete_trapper(&transaction, *chat_id)?;
+                vec![]
+            }
+            };
+            written.push((*chat_id, revisions));
+        }
+
+        transaction.commit().map_err(sql_error)?;
+        for (chat_id, revisions) in written {
+            self.remember_revisions(chat_id, revisions);
+        }
+
+        Ok(())
+    }
+
+    fn incremental(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trapper::adauga::Expression;
+    use crate::trapper::gind::Thought;
+    use crate::storage::{copy_storage, TempDir};
+    use crate::storage::json::JsonStorage;
+
+    #[test]
+    fn round_trip() {
+        let mut storage = SqliteStorage::open(Path::new(":memory:")).unwrap();
+
+        let mut aliases = Aliases::new();
+        aliases.insert("yeet", -469444439);
+        aliases.insert("aztecii", -469444439);
+        storage.save_aliases(&aliases).unwrap();
+        assert_eq!(storage.load_aliases().unwrap(), aliases);
+
+        let mut trapper = Trapper::new();
+        trapper.commands.push(serde_json::from_str::<Expression>(
+            r#"{"added_by": 1, "expr": {"Variable": "joaco"}, "response": "iane",
+                "group_id": -469444439}"#).unwrap());
+        trapper.thoughts.push(Thought::new("un gind frumos".to_string(), 42, 1000, 0));
+        trapper.add_sequence(42, "Ce faci sacale".to_string());
+        trapper.add_sequence(43, "ce faci ba".to_string());
+        trapper.remember_username("Ian", 42);
+
+        let mut statemap = HashMap::new();
+        statemap.insert(-469444439, trapper);
+        statemap.insert(5, Trapper::new());
+        storage.save_trappers(&statemap, &statemap.keys().copied().collect()).unwrap();
+
+        let loaded = storage.load_trappers().unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(),
+                   serde_json::to_value(&statemap).unwrap());
+
+        // Only the changed groups are written
+        statemap.get_mut(&5).unwrap().thoughts.push(Thought::new("nou".to_string(), 43, 2000, 60));
+        statemap.get_mut(&-469444439).unwrap().thoughts.clear();
+        storage.save_trappers(&statemap, &[5].iter().copied().collect()).unwrap();
+
+        let loaded = storage.load_trappers().unwrap();
+        assert_eq!(loaded[&5].thoughts, vec![Thought::new("nou".to_string(), 43, 2000, 60)]);
+        assert_eq!(loaded[&-469444439].thoughts,
+                   vec![Thought::new("un gind frumos".to_string(), 42, 1000, 0)]);
+
+        // Inside a group, only the rows that changed are written again: the 4 new edges of
+        // the group chain and of the user's chain, both their chains and the deleted thought
+        let trapper = statemap.get_mut(&-469444439).unwrap();
+        trapper.add_sequence(42, "ana are mere".to_string());
+        trapper.thoughts.clear();
+        let changes = |storage: &SqliteStorage| -> i64 {
+            storage.connection.query_row("SELECT total_changes()", NO_PARAMS, |row| { row.get(0) })
+                .unwrap()
+        };
+        let before = changes(&storage);
+        storage.save_trappers(&statemap, &[-469444439].iter().copied().collect()).unwrap();
+        assert_eq!(changes(&storage) - before, 11);
+
+        let loaded = storage.load_trappers().unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(),
+                   serde_json::to_value(&statemap).unwrap());
+
+        // Deleted groups lose all their rows
+        statemap.remove(&5);
+        storage.save_trappers(&statemap, &[5].iter().copied().collect()).unwrap();
+        assert!(!storage.load_trappers().unwrap().contains_key(&5));
+    }
+
+    #[test]
+    fn only_changed_chains() {
+        let mut storage = SqliteStorage::open(Path::new(":memory:")).unwrap();
+        let mut trapper = Trapper::new();
+        trapper.add_sequence(42, "ana are mere".to_string());
+        trapper.add_sequence(43, "ce faci".to_string());
+
+        let mut statemap = HashMap::new();
+        statemap.insert(-5, trapper);
+        let changed: HashSet<i64> = [-5].iter().copied().collect();
+        storage.save_trappers(&statemap, &changed).unwrap();
+
+        // An edge changed behind our back stays as it is while its chain doesn't change
+        let weight = |storage: &SqliteStorage| -> i64 {
+            storage.connection.query_row(
+                "SELECT weight FROM markov_edges WHERE user_id = 43 AND source = 'ce'",
+                NO_PARAMS, |row| { row.get(0) }).unwrap()
+        };
+        storage.connection.execute("UPDATE markov_edges SET weight = 7 WHERE user_id = 43",
+                                   NO_PARAMS).unwrap();
+        statemap.get_mut(&-5).unwrap().add_sequence(42, "ana are pere".to_string());
+        storage.save_trappers(&statemap, &changed).unwrap();
+        assert_eq!(weight(&storage), 7);
+
+        statemap.get_mut(&-5).unwrap().add_sequence(43, "ce faci".to_string());
+        storage.save_trappers(&statemap, &changed).unwrap();
+        assert_eq!(weight(&storage), 2);
+
+        // The edges of a chain that's gone are deleted with it
+        statemap.get_mut(&-5).unwrap().forget_user(43);
+        storage.save_trappers(&statemap, &changed).unwrap();
+        let loaded = storage.load_trappers().unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(),
+                   serde_json::to_value(&statemap).unwrap());
+        let edges: i64 = storage.connection.query_row(
+            "SELECT count(*) FROM markov_edges WHERE user_id = 43", NO_PARAMS,
+            |row| { row.get(0) }).unwrap();
+        assert_eq!(edges, 0);
+    }
+
+    #[test]
+    fn migrated_version() {
+        let directory = TempDir::new("migreaza");
+        let path = directory.join("data.sqlite");
+
+        let mut json = JsonStorage::new(&directory.join("data2.JSON"),
+                                        &directory.join("aliases.JSON"));
+        let mut statemap = HashMap::new();
+        statemap.insert(-5, Trapper::new());
+        json.save_trappers(&statemap, &HashSet::new()).unwrap();
+
+        // What migreaza does, into a new database that is then opened again
+        copy_storage(&mut json, &mut SqliteStorage::open(&path).unwrap()).unwrap();
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        assert_eq!(storage.version(), Ok(FORMAT_VERSION));
+        assert_eq!(storage.load_trappers().unwrap().len(), 1);
+
+        // An empty database counts as the current version too
+        let storage = SqliteStorage::open(Path::new(":memory:")).unwrap();
+        assert_eq!(storage.version(), Ok(FORMAT_VERSION));
+    }
+
+    #[test]
+    fn corrupt_database() {
+        let directory = TempDir::new("sqlite");
+        let path = directory.join("data.sqlite");
+        std::fs::write(&path, "asta nu e o baza de date, e doar un text mai lung ca sa para \
+                               ca ar fi un fisier adevarat, dar de fapt nu este").unwrap();
+
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        assert!(storage.load_trappers().unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 2);
+    }
+}
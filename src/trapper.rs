@@ -1,19 +1,64 @@
-use teloxide::prelude::*;
-
 pub mod adauga;
+pub mod ceva;
 pub mod dao;
+pub mod gind;
+pub mod rol;
+pub mod setari;
+
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::trapper::adauga::Expression;
-use crate::trapper::dao::Markov;
-use rand::thread_rng;
+use crate::trapper::ceva::CevaAction;
+use crate::trapper::dao::{ChainLimits, Markov};
+use crate::trapper::gind::{ScheduledThought, Thought, ThoughtOrder};
+use crate::trapper::rol::Role;
+use crate::trapper::setari::GroupSettings;
+use rand::Rng;
 use rand::prelude::SliceRandom;
 use serde::{Serialize, Deserialize};
 
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| { x.as_secs() })
+        .unwrap_or(0)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Trapper {
     pub commands: Vec<Expression>,
-    pub thoughts: Vec<String>,
+    pub thoughts: Vec<Thought>,
     pub markov: Markov,
+    // Chains per user, the key being the user id (the chat id is the key of the Trapper).
+    // They're what each user taught the group chain, so it can be taken back out
+    #[serde(default)]
+    pub user_markov: HashMap<i64, Markov>,
+    // Lowercase telegram usernames seen in the chat, so we know who /dao @user means
+    #[serde(default)]
+    pub usernames: HashMap<String, i64>,
+    // Users that don't want to be imitated with /dao @user
+    #[serde(default)]
+    pub no_imitation: HashSet<i64>,
+    // Users whose messages aren't learned at all
+    #[serde(default)]
+    pub no_training: HashSet<i64>,
+    #[serde(default)]
+    pub thought_order: ThoughtOrder,
+    // Thoughts waiting to be posted on their own, sorted by the time they're due
+    #[serde(default)]
+    pub scheduled_thoughts: Vec<ScheduledThought>,
+    // Who set each alias of the chat
+    #[serde(default)]
+    pub alias_owners: HashMap<String, i64>,
+    // Users with a role other than member
+    #[serde(default)]
+    pub roles: HashMap<i64, Role>,
+    // Until when the triggers don't answer, 0 if they do
+    #[serde(default)]
+    pub muted_until: u64,
+    #[serde(default)]
+    pub settings: GroupSettings,
 }
 
 impl Trapper {
@@ -22,15 +67,533 @@ impl Trapper {
             commands: vec![],
             thoughts: vec![],
             markov: Markov::new(),
+            user_markov: HashMap::new(),
+            usernames: HashMap::new(),
+            no_imitation: HashSet::new(),
+            no_training: HashSet::new(),
+            thought_order: ThoughtOrder::default(),
+            scheduled_thoughts: vec![],
+            alias_owners: HashMap::new(),
+            roles: HashMap::new(),
+            muted_until: 0,
+            settings: GroupSettings::default(),
+        }
+    }
+
+    // Everything but the triggers, the thoughts and the chains, for the storages that keep
+    // those in their own rows
+    pub fn group_data(&self) -> Trapper {
+        Trapper {
+            commands: vec![],
+            thoughts: vec![],
+            markov: self.markov.without_edges(),
+            user_markov: HashMap::new(),
+            usernames: self.usernames.clone(),
+            no_imitation: self.no_imitation.clone(),
+            no_training: self.no_training.clone(),
+            thought_order: self.thought_order,
+            scheduled_thoughts: self.scheduled_thoughts.clone(),
+            alias_owners: self.alias_owners.clone(),
+            roles: self.roles.clone(),
+            muted_until: self.muted_until,
+            settings: self.settings.clone(),
+        }
+    }
+
+    // What /ceva says: something the group has, picked by the weights of the group. The
+    // thoughts stay where they are, the expired ones and the muted triggers are left out
+    pub fn ceva<R: Rng>(&mut self, now: u64, rng: &mut R) -> Option<String> {
+        let thoughts: Vec<&Thought> = self.thoughts.iter().filter(|x| { !x.is_expired(now) }).collect();
+        let triggers = self.settings.triggers && !self.is_muted(now);
+
+        let available: Vec<CevaAction> = ceva::CEVA_ACTIONS.iter().copied()
+            .filter(|action| {
+                match action {
+                CevaAction::Dao => { self.markov.edge_count() > 0 }
+                CevaAction::Gind => { !thoughts.is_empty() }
+                CevaAction::Comanda => { triggers && !self.commands.is_empty() }
+                CevaAction::Joaco => { true }
+                }
+            })
+            .collect();
+
+        match self.settings.ceva.pick(&available, rng)? {
+        CevaAction::Dao => { self.markov.get_random() }
+        CevaAction::Gind => { thoughts.choose(rng).map(|x| { x.text.clone() }) }
+        CevaAction::Comanda => { self.commands.choose(rng).map(|x| { x.response.clone() }) }
+        CevaAction::Joaco => { Some(crate::constants::JOACO_LINK.to_string()) }
+        }
+    }
+
+    // The response of a random trigger matching the words. The triggers keep their order,
+    // /comenzi numbers them
+    pub fn pick_response<R: Rng>(&self, words: &HashMap<&str, ()>, rng: &mut R) -> Option<String> {
+        let matching: Vec<&Expression> = self.commands.iter()
+            .filter(|x| { x.eval(words) })
+            .collect();
+
+        matching.choose(rng).map(|x| { x.response.clone() })
+    }
+
+    // Takes out the next thought for /gind, in the order chosen by the group. The others
+    // keep the order they were added in
+    pub fn take_thought<R: Rng>(&mut self, rng: &mut R) -> Option<Thought> {
+        if self.thoughts.is_empty() {
+            return None;
+        }
+
+        let index = match self.thought_order {
+        ThoughtOrder::Random => { rng.gen_range(0..self.thoughts.len()) }
+        ThoughtOrder::Fifo => { 0 }
+        ThoughtOrder::Lifo => { self.thoughts.len() - 1 }
+        };
+
+        Some(self.thoughts.remove(index))
+    }
+
+    // Learns a message of the user, unless they opted out. Returns whether it learned it
+    pub fn add_sequence(&mut self, user_id: i64, message: String) -> bool {
+        if !self.can_learn_from(user_id) {
+            return false;
+        }
+
+        self.user_markov.entry(user_id)
+            .or_default()
+            .add_sequence(message.clone());
+        self.markov.add_sequence(message);
+        true
+    }
+
+    pub fn can_learn_from(&self, user_id: i64) -> bool {
+        !self.no_training.contains(&user_id)
+    }
+
+    pub fn set_training(&mut self, user_id: i64, allowed: bool) {
+        if allowed {
+            self.no_training.remove(&user_id);
+        } else {
+            self.no_training.insert(user_id);
+        }
+    }
+
+    // Takes everything the user taught out of the group chain. Returns false if we didn't
+    // learn anything from them
+    pub fn forget_user(&mut self, user_id: i64) -> bool {
+        match self.user_markov.remove(&user_id) {
+        Some(chain) => {
+            self.markov.subtract(&chain);
+            true
+        }
+        None => { false }
+        }
+    }
+
+    // Decays and prunes the group chain and the chains of the users. The chains of the users
+    // follow the clock of the group chain, so /uita_ma subtracts counts decayed just as much
+    pub fn maintain_markov(&mut self, limits: &ChainLimits, now: u64) {
+        let decayed = self.markov.decay_if_due(now, limits.decay_period);
+        let pruned = self.markov.prune(limits.max_states) > 0;
+
+        for markov in self.user_markov.values_mut() {
+            if decayed {
+                markov.halve(false);
+            }
+            if pruned {
+                markov.keep_words_of(&self.markov);
+            }
+            markov.prune(limits.max_states);
+        }
+    }
+
+    // Returns how many words were forgotten from the group chain
+    pub fn forget_word(&mut self, word: &str) -> usize {
+        for markov in self.user_markov.values_mut() {
+            markov.forget(word);
+        }
+
+        self.markov.forget(word)
+    }
+
+    // Whether the user was seen on the chat
+    pub fn knows_user(&self, user_id: i64) -> bool {
+        self.user_markov.contains_key(&user_id) || self.roles.contains_key(&user_id) ||
+            self.usernames.values().any(|x| { *x == user_id })
+    }
+
+    pub fn remember_username(&mut self, username: &str, user_id: i64) {
+        self.usernames.insert(username.to_lowercase(), user_id);
+    }
+
+    pub fn find_user(&self, username: &str) -> Option<i64> {
+        let username = username.trim().trim_start_matches('@').to_lowercase();
+        self.usernames.get(&username).copied()
+    }
+
+    // The user as @username if we know it, or the id otherwise
+    pub fn user_name(&self, user_id: i64) -> String {
+        match self.usernames.iter().find(|x| { *x.1 == user_id }) {
+        Some((username, _)) => { format!("@{}", username) }
+        None => { user_id.to_string() }
+        }
+    }
+
+    // Who set the alias
+    pub fn alias_owner(&self, alias: &str) -> Option<String> {
+        self.alias_owners.get(alias).map(|x| { self.user_name(*x) })
+    }
+
+    pub fn role_of(&self, user_id: i64) -> Role {
+        self.roles.get(&user_id).copied().unwrap_or_default()
+    }
+
+    pub fn set_role(&mut self, user_id: i64, role: Role) {
+        if role == Role::Member {
+            self.roles.remove(&user_id);
+        } else {
+            self.roles.insert(user_id, role);
+        }
+    }
+
+    pub fn mute(&mut self, now: u64, seconds: u64) {
+        self.muted_until = now + seconds;
+    }
+
+    pub fn unmute(&mut self) {
+        self.muted_until = 0;
+    }
+
+    pub fn is_muted(&self, now: u64) -> bool {
+        now < self.muted_until
+    }
+
+    pub fn can_imitate(&self, user_id: i64) -> bool {
+        !self.no_imitation.contains(&user_id)
+    }
+
+    // The chain of the user stays and keeps learning, /uita_ma still needs it
+    pub fn set_imitation(&mut self, user_id: i64, allowed: bool) {
+        if allowed {
+            self.no_imitation.remove(&user_id);
+        } else {
+            self.no_imitation.insert(user_id);
+        }
+    }
+
+    pub fn get_random_from(&mut self, user_id: i64) -> Option<String> {
+        if !self.can_imitate(user_id) {
+            return None;
+        }
+
+        match self.user_markov.get_mut(&user_id) {
+        Some(markov) => { markov.get_random() }
+        None => { None }
+        }
+    }
+
+    // Drops the thoughts that expired before anyone saw them, returns how many
+    pub fn drop_expired_thoughts(&mut self, now: u64) -> usize {
+        let before = self.thoughts.len();
+        self.thoughts.retain(|x| { !x.is_expired(now) });
+
+        before - self.thoughts.len()
+    }
+
+    // Drops the thoughts added by @username, or the ones containing the text. Returns how
+    // many were dropped
+    pub fn remove_thoughts(&mut self, what: &str) -> usize {
+        let what = what.trim();
+        if what.is_empty() {
+            return 0;
         }
+
+        let author = if what.starts_with('@') {
+            match self.find_user(what) {
+            Some(x) => { Some(x) }
+            None => { return 0; }
+            }
+        } else {
+            None
+        };
+
+        let before = self.thoughts.len() + self.scheduled_thoughts.len();
+        self.thoughts.retain(|x| { !x.matches(what, author) });
+        self.scheduled_thoughts.retain(|x| { !x.thought.matches(what, author) });
+
+        before - self.thoughts.len() - self.scheduled_thoughts.len()
+    }
+
+    pub fn schedule_thought(&mut self, thought: Thought, deliver_at: u64) {
+        let index = self.scheduled_thoughts.iter()
+            .position(|x| { x.deliver_at > deliver_at })
+            .unwrap_or(self.scheduled_thoughts.len());
+
+        self.scheduled_thoughts.insert(index, ScheduledThought { deliver_at, thought });
     }
 
-    pub fn shuffle_commands(&mut self) {
-        self.commands.shuffle(&mut thread_rng());
+    // Takes out the scheduled thoughts that are due, the oldest first
+    pub fn take_due_thoughts(&mut self, now: u64) -> Vec<Thought> {
+        let due = self.scheduled_thoughts.iter()
+            .take_while(|x| { x.deliver_at <= now })
+            .count();
+
+        self.scheduled_thoughts.drain(..due).map(|x| { x.thought }).collect()
     }
 
-    pub fn shuffle_thoughts(&mut self) {
-        self.commands.shuffle(&mut thread_rng());
+    // The part of the group that makes sense in another group: the triggers, the thoughts
+    // and the group chain. What we know about the users stays here
+    pub fn export(&self) -> Trapper {
+        Trapper {
+            commands: self.commands.clone(),
+            thoughts: self.thoughts.clone(),
+            markov: self.markov.clone(),
+            ..Trapper::new()
+        }
+    }
+
+    // Adds an exported group to this one, which lives in chat_id. The triggers and the
+    // thoughts we already have are skipped, the chains are added together. Returns how
+    // many triggers and thoughts were new
+    pub fn merge(&mut self, chat_id: i64, other: Trapper) -> (usize, usize) {
+        let mut new_commands = 0;
+        for mut command in other.commands {
+            command.group_id = chat_id;
+
+            if !self.commands.contains(&command) {
+                self.commands.push(command);
+                new_commands += 1;
+            }
+        }
+
+        let mut new_thoughts = 0;
+        for thought in other.thoughts {
+            if !self.thoughts.iter().any(|x| { x.text == thought.text }) {
+                self.thoughts.push(thought);
+                new_thoughts += 1;
+            }
+        }
+
+        self.markov.merge(&other.markov);
+
+        (new_commands, new_thoughts)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn with_thoughts(count: usize) -> Trapper {
+        let mut trapper = Trapper::new();
+        for i in 0..count {
+            trapper.thoughts.push(Thought::new(i.to_string(), 1, i as u64, 0));
+        }
+
+        trapper
+    }
+
+    #[test]
+    fn random_thoughts() {
+        let mut rng = StdRng::seed_from_u64(1256262);
+        let mut counts = [0; 4];
+
+        for _ in 0..4000 {
+            let mut trapper = with_thoughts(4);
+            let thought = trapper.take_thought(&mut rng).unwrap();
+            counts[thought.text.parse::<usize>().unwrap()] += 1;
+
+            assert_eq!(trapper.thoughts.len(), 3);
+            assert!(trapper.thoughts.windows(2).all(|x| { x[0].added_at < x[1].added_at }));
+        }
+
+        // Every thought gets about a quarter of the picks, not only the last one
+        for count in counts.iter() {
+            assert!((900..1100).contains(count), "{:?}", counts);
+        }
+    }
+
+    #[test]
+    fn ordered_thoughts() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let mut trapper = with_thoughts(3);
+        trapper.thought_order = ThoughtOrder::Fifo;
+        let taken: Vec<String> = std::iter::from_fn(|| { trapper.take_thought(&mut rng) })
+            .map(|x| { x.text })
+            .collect();
+        assert_eq!(taken, vec!["0", "1", "2"]);
+
+        let mut trapper = with_thoughts(3);
+        trapper.thought_order = ThoughtOrder::Lifo;
+        let taken: Vec<String> = std::iter::from_fn(|| { trapper.take_thought(&mut rng) })
+            .map(|x| { x.text })
+            .collect();
+        assert_eq!(taken, vec!["2", "1", "0"]);
+
+        assert_eq!(trapper.take_thought(&mut rng), None);
+    }
+
+    #[test]
+    fn scheduled_thoughts() {
+        let mut trapper = with_thoughts(0);
+        trapper.schedule_thought(Thought::new("doi".to_string(), 1, 0, 0), 200);
+        trapper.schedule_thought(Thought::new("unu".to_string(), 1, 0, 0), 100);
+        trapper.schedule_thought(Thought::new("trei".to_string(), 1, 0, 0), 300);
+
+        assert!(trapper.take_due_thoughts(99).is_empty());
+        let due: Vec<String> = trapper.take_due_thoughts(200).into_iter()
+            .map(|x| { x.text })
+            .collect();
+        assert_eq!(due, vec!["unu", "doi"]);
+        assert_eq!(trapper.scheduled_thoughts.len(), 1);
+
+        assert_eq!(trapper.remove_thoughts("tre"), 1);
+        assert!(trapper.scheduled_thoughts.is_empty());
+    }
+
+    #[test]
+    fn alias_owners() {
+        let mut trapper = Trapper::new();
+        trapper.alias_owners.insert("yeet".to_string(), 5);
+        trapper.alias_owners.insert("aztecii".to_string(), 6);
+        trapper.remember_username("Ana", 5);
+
+        assert_eq!(trapper.alias_owner("yeet"), Some("@ana".to_string()));
+        assert_eq!(trapper.alias_owner("aztecii"), Some("6".to_string()));
+        assert_eq!(trapper.alias_owner("altceva"), None);
+
+        assert!(trapper.knows_user(5));
+        assert!(!trapper.knows_user(6));
+        trapper.add_sequence(6, "ana are mere".to_string());
+        assert!(trapper.knows_user(6));
+    }
+
+    #[test]
+    fn roles() {
+        let mut trapper = Trapper::new();
+        assert_eq!(trapper.role_of(5), Role::Member);
+
+        trapper.set_role(5, Role::Moderator);
+        trapper.set_role(6, Role::Banned);
+        assert_eq!(trapper.role_of(5), Role::Moderator);
+        assert_eq!(trapper.role_of(6), Role::Banned);
+
+        trapper.set_role(6, Role::Member);
+        assert_eq!(trapper.roles.len(), 1);
+    }
+
+    #[test]
+    fn training_opt_out() {
+        let mut trapper = Trapper::new();
+        trapper.add_sequence(5, "ana are mere".to_string());
+        trapper.add_sequence(6, "ana are pere".to_string());
+
+        trapper.set_training(6, false);
+        assert!(!trapper.add_sequence(6, "ion are mere".to_string()));
+        assert_eq!(trapper.markov.sentences(), 2);
+        assert_eq!(trapper.user_markov[&6].sentences(), 1);
+
+        // Not being imitated doesn't lose what we need to forget the user
+        trapper.set_imitation(6, false);
+        assert!(trapper.forget_user(6));
+        assert!(!trapper.forget_user(6));
+        assert_eq!(trapper.markov, trapper.user_markov[&5]);
+
+        trapper.set_training(6, true);
+        trapper.add_sequence(6, "ion are mere".to_string());
+        assert_eq!(trapper.markov.sentences(), 2);
+    }
+
+    #[test]
+    fn forget_after_decay() {
+        let mut trapper = Trapper::new();
+        for _ in 0..3 {
+            trapper.add_sequence(5, "ana are mere".to_string());
+        }
+        trapper.add_sequence(6, "ana are mere".to_string());
+
+        let limits = ChainLimits { decay_period: 10, max_states: 0 };
+        trapper.maintain_markov(&limits, 100);
+        trapper.maintain_markov(&limits, 110);
+
+        // 4 halved to 2 for the group, 3 to 1 for the first user and 1 to 0 for the other.
+        // The group keeps something from the other user after forgetting the first one
+        assert!(trapper.forget_user(5));
+        assert_eq!(trapper.markov.edges().map(|x| { x.2 }).collect::<Vec<i64>>(), vec![1; 4]);
+        assert!(trapper.markov.get_random().is_some());
+    }
+
+    #[test]
+    fn ceva() {
+        let mut trapper = Trapper::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        for action in ceva::CEVA_ACTIONS {
+            trapper.settings.ceva.set(*action, 0).unwrap();
+        }
+        assert_eq!(trapper.ceva(0, &mut rng), None);
+
+        // Only what the group has is picked
+        trapper.settings.ceva.set(CevaAction::Gind, 1).unwrap();
+        trapper.settings.ceva.set(CevaAction::Joaco, 1).unwrap();
+        for _ in 0..10 {
+            assert_eq!(trapper.ceva(0, &mut rng), Some(crate::constants::JOACO_LINK.to_string()));
+        }
+
+        trapper.thoughts.push(Thought::new("un gind".to_string(), 5, 0, 0));
+        trapper.settings.ceva.set(CevaAction::Joaco, 0).unwrap();
+        assert_eq!(trapper.ceva(0, &mut rng), Some("un gind".to_string()));
+        assert_eq!(trapper.thoughts.len(), 1);
+
+        // Expired thoughts aren't said, even if /gind didn't drop them yet
+        trapper.thoughts = vec![Thought::new("expirat".to_string(), 5, 0, 60)];
+        assert_eq!(trapper.ceva(30, &mut rng), Some("expirat".to_string()));
+        assert_eq!(trapper.ceva(60, &mut rng), None);
+
+        // Neither are the triggers of a muted group
+        trapper.commands.push(serde_json::from_str::<Expression>(
+            r#"{"added_by": 5, "expr": {"Variable": "ceva"}, "response": "comanda",
+                "group_id": -5}"#).unwrap());
+        trapper.settings.ceva.set(CevaAction::Gind, 0).unwrap();
+        trapper.settings.ceva.set(CevaAction::Comanda, 1).unwrap();
+        assert_eq!(trapper.ceva(0, &mut rng), Some("comanda".to_string()));
+        trapper.mute(0, 60);
+        assert_eq!(trapper.ceva(30, &mut rng), None);
+        assert_eq!(trapper.ceva(60, &mut rng), Some("comanda".to_string()));
+    }
+
+    #[test]
+    fn mute() {
+        let mut trapper = Trapper::new();
+        assert!(!trapper.is_muted(100));
+
+        trapper.mute(100, 60);
+        assert!(trapper.is_muted(159));
+        assert!(!trapper.is_muted(160));
+
+        trapper.mute(100, 60);
+        trapper.unmute();
+        assert!(!trapper.is_muted(100));
+    }
+
+    #[test]
+    fn picked_responses() {
+        let mut trapper = Trapper::new();
+        for &(word, response) in &[("ceva", "unu"), ("altceva", "doi"), ("ceva", "trei")] {
+            trapper.commands.push(serde_json::from_str::<Expression>(&format!(
+                r#"{{"added_by": 5, "expr": {{"Variable": "{}"}}, "response": "{}",
+                    "group_id": -5}}"#, word, response)).unwrap());
+        }
+
+        let words: HashMap<&str, ()> = vec![("ceva", ())].into_iter().collect();
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..20 {
+            let response = trapper.pick_response(&words, &mut rng).unwrap();
+            assert!(response == "unu" || response == "trei");
+        }
+        assert_eq!(trapper.pick_response(&HashMap::new(), &mut rng), None);
+
+        // Picking doesn't change the numbers from /comenzi
+        assert_eq!(trapper.commands[0].response, "unu");
+    }
+}
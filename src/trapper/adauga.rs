@@ -2,6 +2,7 @@ use std::str::FromStr;
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use crate::ALIASES;
+use crate::aliases::Aliases;
 use crate::constants::*;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -43,6 +44,26 @@ impl ExpressionTree {
         }
         }
     }
+
+    // The expression as it would be written, with the needed parentheses
+    fn describe(&self) -> String {
+        match self {
+        ExpressionTree::Variable(word) => { word.clone() }
+        ExpressionTree::OrSign(children) => {
+            format!("{} | {}", children.0.describe(), children.1.describe_operand())
+        }
+        ExpressionTree::AndSign(children) => {
+            format!("{} & {}", children.0.describe(), children.1.describe_operand())
+        }
+        }
+    }
+
+    fn describe_operand(&self) -> String {
+        match self {
+        ExpressionTree::Variable(word) => { word.clone() }
+        _ => { format!("({})", self.describe()) }
+        }
+    }
 }
 
 // Expression Id~ExpressionTree~message
@@ -55,7 +76,7 @@ fn parse_variable(mut token: &[u8]) -> Result<(ExpressionTree, &[u8]), String> {
         token = &token[1..];
 
         let (expression, remainder) = parse_expr(token)?;
-        token = &remainder;
+        token = remainder;
 
         if !token.is_empty() && token[0] == b')' {
             token = &token[1..];
@@ -79,14 +100,14 @@ fn parse_variable(mut token: &[u8]) -> Result<(ExpressionTree, &[u8]), String> {
 
 fn parse_expr(mut token: &[u8]) -> Result<(ExpressionTree, &[u8]), String> {
     let (mut expression, remainder) = parse_variable(token)?;
-    token = &remainder;
+    token = remainder;
 
     while !token.is_empty() && (token[0] == b'&' || token[0] == b'|') {
         let operation = token[0];
         token = &token[1..];
         
         let (additional_expression, remainder) = parse_variable(token)?;
-        token = &remainder;
+        token = remainder;
     
         expression = match operation {
         b'&' => { ExpressionTree::AndSign(Box::new((expression, additional_expression))) }
@@ -109,38 +130,44 @@ fn parse(token: String) -> Result<ExpressionTree, String> {
 }
 
 impl Expression {
+    // The spaces in the expression don't matter
+    pub fn new(added_by: i64, group_id: i64, expr: &str, response: String) -> Result<Expression, String> {
+        let mut expr = expr.to_string();
+        expr.retain(|x| { x != ' ' });
+
+        Ok(Expression {
+            added_by,
+            group_id,
+            expr: parse(expr)?,
+            response,
+        })
+    }
+
     pub fn eval(&self, words: &HashMap<&str, ()>) -> bool {
         self.expr.eval(words)
     }
-}
 
-impl FromStr for Expression {
-    type Err = String;
+    pub fn describe(&self) -> String {
+        format!("{} -> {}", self.expr.describe(), self.response)
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Expression {
+    // Parses [user id]~[alias]~[expression]~[response], finding the group in `aliases`
+    pub fn parse(s: &str, aliases: &Aliases) -> Result<Expression, String> {
         let tokens: Vec<&str> = s.split('~').collect();
         
         if tokens.len() != 4 {
             Err(BAD_SEPARATORS.to_string())
         } else {
-            match ALIASES.lock().unwrap().get_by_left(tokens[1].trim()) {
+            match aliases.get(tokens[1]) {
             Some(x) => {
-                Ok(Expression {
-                    added_by: {
-                        let x = i64::from_str(tokens[0].trim());
-                        match x {
-                        Err(x) => { return Err(format!("{}", x).to_string()); }
-                        Ok(x)  => { x }
-                        }
-                    },
-                    group_id: *x,
-                    expr: {
-                        let mut cpy = tokens[2].to_string();
-                        cpy.retain(|x| { x != ' ' });
-                        parse(cpy)?
-                    },
-                    response: tokens[3].to_string(),
-                })
+                let added_by = match i64::from_str(tokens[0].trim()) {
+                Err(x) => { return Err(format!("{}", x)); }
+                Ok(x)  => { x }
+                };
+
+                Expression::new(added_by, x, tokens[2], tokens[3].to_string())
             }
             None => {
                 Err(WRONG_ALIAS.to_string())
@@ -150,14 +177,30 @@ impl FromStr for Expression {
     }
 }
 
+impl FromStr for Expression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Expression::parse(s, &ALIASES.lock().unwrap())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // The tests use the alias of the group where the bot was first tested, without going
+    // through the aliases of the bot
+    fn parse(s: &str) -> Result<Expression, String> {
+        let mut aliases = Aliases::new();
+        aliases.insert("yeet", -469444439);
+        Expression::parse(s, &aliases)
+    }
+
     #[test]
     fn good_test() {
         assert_eq!(
-            Expression::from_str("1256262~yeet~asDf    |    milsugi     & (coaie | pula)~test"), 
+            parse("1256262~yeet~asDf    |    milsugi     & (coaie | pula)~test"), 
             Ok(Expression {
                 group_id: -469444439,
                 added_by: 1256262,
@@ -179,32 +222,50 @@ mod tests {
 
     #[test]
     fn bad_separators() {
-        assert_eq!(Expression::from_str("125~yeet~asdf|milsugi|(coaie|pula)~test~test"),
+        assert_eq!(parse("125~yeet~asdf|milsugi|(coaie|pula)~test~test"),
             Err(BAD_SEPARATORS.to_string()));
     }
 
     #[test]
     fn not_enough_parantheses() {
-        assert_eq!(Expression::from_str("1262~yeet~asdf|milsugi|(coaie|pula~test"),
+        assert_eq!(parse("1262~yeet~asdf|milsugi|(coaie|pula~test"),
             Err(BAD_PARANTHESES.to_string()));
     }
     
     #[test]
     fn too_many_parantheses() {
-        assert_eq!(Expression::from_str("1262~yeet~asdf|milsugi|(coaie|pula))~test"),
+        assert_eq!(parse("1262~yeet~asdf|milsugi|(coaie|pula))~test"),
             Err(BAD_PARANTHESES.to_string()));
     }
 
     #[test]
     fn bad_operator() {
-        assert_eq!(Expression::from_str("1262~yeet~asdf|milsugi^(coaie|pula)~test"),
+        assert_eq!(parse("1262~yeet~asdf|milsugi^(coaie|pula)~test"),
             Err(BAD_PARANTHESES.to_string()));
     }
 
     #[test]
     fn illegal_characters() {
-        assert_eq!(Expression::from_str("1262~yeet~asdf|.milsugi|(coaie|pula)~test"),
+        assert_eq!(parse("1262~yeet~asdf|.milsugi|(coaie|pula)~test"),
             Err(BAD_CHARACTERS.to_string()));
     }
-}
 
+    #[test]
+    fn new_test() {
+        assert_eq!(Expression::new(1, -469444439, "a & (b|c)", "ceva".to_string()),
+                   parse("1~yeet~a&(b|c)~ceva"));
+        assert_eq!(Expression::new(1, -5, "a & (b", "ceva".to_string()),
+                   Err(BAD_PARANTHESES.to_string()));
+        assert_eq!(Expression::new(1, -5, "", "ceva".to_string()),
+                   Err(BAD_CHARACTERS.to_string()));
+    }
+
+    #[test]
+    fn describe_test() {
+        let expression = parse("1~yeet~a&(b|c)|d~ceva").unwrap();
+        assert_eq!(expression.describe(), "a & (b | c) | d -> ceva");
+
+        let again = parse(&format!("1~yeet~{}~ceva", expression.expr.describe()));
+        assert_eq!(again, Ok(expression));
+    }
+}
@@ -0,0 +1,132 @@
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+// What /ceva may do
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CevaAction {
+    // A sentence from the chain of the group
+    Dao,
+    // A thought, without taking it out
+    Gind,
+    // The response of a trigger
+    Comanda,
+    Joaco,
+}
+
+pub const CEVA_ACTIONS: &[CevaAction] = &[CevaAction::Dao, CevaAction::Gind, CevaAction::Comanda,
+                                          CevaAction::Joaco];
+
+// The biggest weight, so nobody overflows the sum
+pub const MAX_WEIGHT: u32 = 100;
+
+impl CevaAction {
+    pub fn from_name(name: &str) -> Option<CevaAction> {
+        CEVA_ACTIONS.iter().copied().find(|x| { x.name() == name.trim().to_lowercase() })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+        CevaAction::Dao => { "dao" }
+        CevaAction::Gind => { "gind" }
+        CevaAction::Comanda => { "comanda" }
+        CevaAction::Joaco => { "joaco" }
+        }
+    }
+}
+
+// How likely every action is, relative to the others. 0 turns an action off
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct CevaWeights {
+    pub dao: u32,
+    pub gind: u32,
+    pub comanda: u32,
+    pub joaco: u32,
+}
+
+impl Default for CevaWeights {
+    fn default() -> CevaWeights {
+        CevaWeights {
+            dao: 1,
+            gind: 1,
+            comanda: 1,
+            joaco: 1,
+        }
+    }
+}
+
+impl CevaWeights {
+    pub fn get(&self, action: CevaAction) -> u32 {
+        match action {
+        CevaAction::Dao => { self.dao }
+        CevaAction::Gind => { self.gind }
+        CevaAction::Comanda => { self.comanda }
+        CevaAction::Joaco => { self.joaco }
+        }
+    }
+
+    pub fn set(&mut self, action: CevaAction, weight: u32) -> Result<(), String> {
+        if weight > MAX_WEIGHT {
+            return Err(format!("Ponderea poate sa fie intre 0 si {}", MAX_WEIGHT));
+        }
+
+        match action {
+        CevaAction::Dao => { self.dao = weight; }
+        CevaAction::Gind => { self.gind = weight; }
+        CevaAction::Comanda => { self.comanda = weight; }
+        CevaAction::Joaco => { self.joaco = weight; }
+        }
+
+        Ok(())
+    }
+
+    // One of the available actions, each as likely as its weight. None if all of them
+    // have weight 0
+    pub fn pick<R: Rng>(&self, available: &[CevaAction], rng: &mut R) -> Option<CevaAction> {
+        let total: u32 = available.iter().map(|x| { self.get(*x) }).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut left = rng.gen_range(0..total);
+        for action in available {
+            let weight = self.get(*action);
+            if left < weight {
+                return Some(*action);
+            }
+            left -= weight;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn weights() {
+        let mut weights = CevaWeights::default();
+        weights.set(CevaAction::Dao, 0).unwrap();
+        weights.set(CevaAction::from_name("GIND").unwrap(), 3).unwrap();
+        assert!(weights.set(CevaAction::Joaco, MAX_WEIGHT + 1).is_err());
+        assert_eq!(CevaAction::from_name("altceva"), None);
+
+        let mut rng = StdRng::seed_from_u64(1256262);
+        let mut picked = vec![0; CEVA_ACTIONS.len()];
+        for _ in 0..1000 {
+            let action = weights.pick(CEVA_ACTIONS, &mut rng).unwrap();
+            picked[CEVA_ACTIONS.iter().position(|x| { *x == action }).unwrap()] += 1;
+        }
+
+        // dao is off, gind comes up about three times as often as the others
+        assert_eq!(picked[0], 0);
+        assert!(picked[1] > 2 * picked[2] && picked[1] > 2 * picked[3]);
+
+        assert_eq!(weights.pick(&[CevaAction::Dao], &mut rng), None);
+        assert_eq!(weights.pick(&[], &mut rng), None);
+    }
+}
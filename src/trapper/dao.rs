@@ -1,16 +1,107 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use serde::{Serialize, Deserialize};
-use rand::prelude::*;
+use crate::config::{env_flag, env_number};
 
-#[derive(Debug, PartialEq, Default, Serialize, Deserialize, Clone)]
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+// Tells the copies of a chain apart from the ones that changed since. Every change and
+// every chain that gets loaded takes a new one, so a storage only has to write the chains
+// whose revision isn't the one it last saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Revision(u64);
+
+impl Default for Revision {
+    fn default() -> Revision {
+        Revision(NEXT_REVISION.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Markov {
     chain: HashMap<String, Vec<(String, i64)>>,
+    // Unix time of the last decay, 0 if the chain never decayed
+    #[serde(default)]
+    last_decay: u64,
+    // How many messages were learned, chains from before this was counted start at 0
+    #[serde(default)]
+    sentences: u64,
+    #[serde(skip)]
+    revision: Revision,
+}
+
+// Two chains are the same if they know the same things, whatever their revisions
+impl PartialEq for Markov {
+    fn eq(&self, other: &Markov) -> bool {
+        self.chain == other.chain && self.last_decay == other.last_decay
+            && self.sentences == other.sentences
+    }
+}
+
+// How the chains are kept from growing forever
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChainLimits {
+    // Seconds between two decays, 0 means the counts never decay
+    pub decay_period: u64,
+    // Number of words a chain may know before the least used ones are pruned, 0 means no limit
+    pub max_states: usize,
+}
+
+impl Default for ChainLimits {
+    fn default() -> ChainLimits {
+        ChainLimits {
+            decay_period: 30 * 24 * 60 * 60,
+            max_states: 20000,
+        }
+    }
+}
+
+impl ChainLimits {
+    // MARKOV_DECAY_DAYS and MARKOV_MAX_WORDS overwrite the defaults
+    pub fn from_env() -> ChainLimits {
+        let default = ChainLimits::default();
+
+        ChainLimits {
+            decay_period: env_number("MARKOV_DECAY_DAYS", default.decay_period / (24 * 60 * 60))
+                * 24 * 60 * 60,
+            max_states: env_number("MARKOV_MAX_WORDS", default.max_states as u64) as usize,
+        }
+    }
+}
+
+// Words are compared without case and punctuation when forgetting them
+fn normalize_word(word: &str) -> String {
+    word.chars()
+        .filter(|x| { x.is_alphanumeric() })
+        .flat_map(|x| { x.to_lowercase() })
+        .collect()
 }
 
 impl Markov {
     pub fn new() -> Markov {
         Markov {
-            chain: HashMap::new()
+            chain: HashMap::new(),
+            last_decay: 0,
+            sentences: 0,
+            revision: Revision::default(),
+        }
+    }
+
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    fn touch(&mut self) {
+        self.revision = Revision::default();
+    }
+
+    // The same chain without its edges, for the storages that keep the edges apart
+    pub fn without_edges(&self) -> Markov {
+        Markov {
+            chain: HashMap::new(),
+            last_decay: self.last_decay,
+            sentences: self.sentences,
+            revision: self.revision,
         }
     }
 
@@ -22,13 +113,14 @@ impl Markov {
         
         words.insert(0, String::from(""));
         words.push(String::from(""));
+        self.sentences += 1;
+        self.touch();
     
         for i in 0..words.len() - 1 {
             let str1 = words[i].clone();
             let str2 = words[i + 1].clone();
 
-            let mut id = 0;
-            let mut edge = self.chain.remove(&str1);
+            let edge = self.chain.remove(&str1);
             
             let to_insert = match edge {
             Some(mut x) => {
@@ -64,13 +156,13 @@ impl Markov {
         Some(x) => {
             let mut sum: i64 = 0;
             for edge in &x {
-                sum = sum + edge.1;
+                sum += edge.1;
             }
             let mut id: i64 = rand::random::<i64>() % sum;
             let mut res_id = 0;
 
             while id >= x[res_id].1 {
-                id = id - x[res_id].1;
+                id -= x[res_id].1;
                 res_id += 1;
             }
 
@@ -92,23 +184,356 @@ impl Markov {
         let mut final_message = last_string.clone();
 
         while last_string != String::new() {
-            last_string = match self.get_random_edge(last_string) {
-            None => { return None; }
-            Some(x) => { x }
-            };
+            // Pruning and forgetting may leave words with nowhere to go, so we just stop there
+            last_string = self.get_random_edge(last_string).unwrap_or_default();
             
             if last_string != String::new() {
-                final_message = final_message + &" " + &last_string;
+                final_message = final_message + " " + &last_string;
             }
         }
 
         Some(final_message)
     }
+
+    // Every edge of the chain as (from, to, weight), the start and the end of the messages
+    // being empty strings
+    pub fn edges(&self) -> impl Iterator<Item = (&String, &String, i64)> + '_ {
+        self.chain.iter()
+            .flat_map(|(from, edges)| { edges.iter().map(move |(to, weight)| { (from, to, *weight) }) })
+    }
+
+    // Adds weight to an edge, creating it if it doesn't exist yet
+    pub fn add_edge(&mut self, from: String, to: String, weight: i64) {
+        self.touch();
+        let edges = self.chain.entry(from).or_default();
+
+        match edges.iter_mut().find(|x| { x.0 == to }) {
+        Some(edge) => { edge.1 += weight; }
+        None => { edges.push((to, weight)); }
+        }
+    }
+
+    // Learns everything the other chain knows, as if we saw its messages too
+    pub fn merge(&mut self, other: &Markov) {
+        for (from, to, weight) in other.edges() {
+            self.add_edge(from.clone(), to.clone(), weight);
+        }
+
+        self.sentences += other.sentences;
+        self.touch();
+    }
+
+    // Every weight has to be positive and the weights of a word have to add up without
+    // overflowing, otherwise picking the next word breaks. Chains from outside, like
+    // /import, are checked with this
+    pub fn check_weights(&self) -> Result<(), String> {
+        for (from, edges) in &self.chain {
+            if edges.iter().any(|x| { x.1 <= 0 }) {
+                return Err(format!("Dupa \"{}\" vine ceva cu ponderea zero sau negativa", from));
+            }
+            if state_weight(edges).is_none() {
+                return Err(format!("Ponderile de dupa \"{}\" sunt prea mari", from));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether merging the other chain into this one keeps the weights of every word from
+    // overflowing. Both chains have to pass check_weights
+    pub fn can_merge(&self, other: &Markov) -> bool {
+        other.chain.iter().all(|(from, edges)| {
+            let ours = self.chain.get(from).and_then(|x| { state_weight(x) }).unwrap_or(0);
+            state_weight(edges).and_then(|x| { x.checked_add(ours) }).is_some()
+        })
+    }
+
+    // Takes back what the other chain taught this one, the opposite of merge. The chains may
+    // have decayed and been pruned differently, so nothing goes below zero
+    pub fn subtract(&mut self, other: &Markov) {
+        for (from, to, weight) in other.edges() {
+            let edges = match self.chain.get_mut(from) {
+            Some(x) => { x }
+            None => { continue; }
+            };
+
+            if let Some(edge) = edges.iter_mut().find(|x| { &x.0 == to }) {
+                edge.1 -= weight;
+            }
+            edges.retain(|x| { x.1 > 0 });
+            if edges.is_empty() {
+                self.chain.remove(from);
+            }
+        }
+
+        self.sentences = self.sentences.saturating_sub(other.sentences);
+        self.touch();
+    }
+
+    // Number of different words known, without the start and end of the messages
+    pub fn vocabulary_size(&self) -> usize {
+        self.chain.keys().filter(|x| { !x.is_empty() }).count()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.chain.values().map(|x| { x.len() }).sum()
+    }
+
+    pub fn sentences(&self) -> u64 {
+        self.sentences
+    }
+
+    pub fn serialized_size(&self) -> usize {
+        serde_json::to_string(self).map(|x| { x.len() }).unwrap_or(0)
+    }
+
+    // The most used words, counted by how many times they were reached
+    pub fn top_words(&self, count: usize) -> Vec<(String, i64)> {
+        let mut usage: HashMap<&String, i64> = HashMap::new();
+        for (word, weight) in self.chain.values().flatten() {
+            if !word.is_empty() {
+                *usage.entry(word).or_insert(0) += weight;
+            }
+        }
+
+        let mut usage: Vec<(String, i64)> = usage.into_iter()
+            .map(|(word, weight)| { (word.clone(), weight) })
+            .collect();
+        usage.sort_by(|a, b| { b.1.cmp(&a.1).then_with(|| { a.0.cmp(&b.0) }) });
+        usage.truncate(count);
+
+        usage
+    }
+
+    // The most used pairs of consecutive words
+    pub fn top_transitions(&self, count: usize) -> Vec<(String, String, i64)> {
+        let mut transitions: Vec<(String, String, i64)> = self.chain.iter()
+            .filter(|(from, _)| { !from.is_empty() })
+            .flat_map(|(from, edges)| {
+                edges.iter()
+                    .filter(|x| { !x.0.is_empty() })
+                    .map(move |(to, weight)| { (from.clone(), to.clone(), *weight) })
+            })
+            .collect();
+        transitions.sort_by(|a, b| {
+            b.2.cmp(&a.2).then_with(|| { (&a.0, &a.1).cmp(&(&b.0, &b.1)) })
+        });
+        transitions.truncate(count);
+
+        transitions
+    }
+
+    // The words that may follow the given one (without caring about case), with their
+    // probabilities. The end of the message is an empty string
+    pub fn next_words(&self, word: &str) -> Vec<(String, f64)> {
+        let word = word.to_lowercase();
+        let mut weights: HashMap<&String, i64> = HashMap::new();
+
+        for (_, edges) in self.chain.iter().filter(|(x, _)| { x.to_lowercase() == word }) {
+            for (to, weight) in edges {
+                *weights.entry(to).or_insert(0) += weight;
+            }
+        }
+
+        let total: i64 = weights.values().sum();
+        let mut next: Vec<(String, f64)> = weights.into_iter()
+            .map(|(to, weight)| { (to.clone(), weight as f64 / total as f64) })
+            .collect();
+        next.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| { a.0.cmp(&b.0) })
+        });
+
+        next
+    }
+
+    // Halves every count (rounding up), so old favourites slowly lose their weight in front
+    // of newer messages. Returns true if the chain decayed
+    pub fn decay_if_due(&mut self, now: u64, period: u64) -> bool {
+        if period == 0 {
+            return false;
+        }
+
+        if self.last_decay == 0 {
+            self.last_decay = now;
+            self.touch();
+            return false;
+        }
+
+        if now < self.last_decay + period {
+            return false;
+        }
+
+        self.halve(true);
+        self.last_decay = now;
+
+        true
+    }
+
+    // The chains of the users decay with the one of their group, rounding down. That way the
+    // group always keeps at least what its users taught it, and taking a user out of it
+    // never takes what the others taught
+    pub fn halve(&mut self, round_up: bool) {
+        let extra = if round_up { 1 } else { 0 };
+        for edges in self.chain.values_mut() {
+            for edge in edges.iter_mut() {
+                edge.1 = (edge.1 + extra) / 2;
+            }
+            edges.retain(|x| { x.1 > 0 });
+        }
+        self.chain.retain(|_, edges| { !edges.is_empty() });
+        self.touch();
+    }
+
+    // Forgets the words the other chain doesn't know, after it was pruned
+    pub fn keep_words_of(&mut self, other: &Markov) {
+        let removed: HashSet<String> = self.chain.keys()
+            .chain(self.chain.values().flatten().map(|x| { &x.0 }))
+            .filter(|x| { !x.is_empty() && !other.chain.contains_key(*x) })
+            .cloned()
+            .collect();
+
+        self.remove_words(&removed);
+    }
+
+    // Removes the least used words until the chain knows at most 9/10 of max_states words,
+    // so we don't prune again on every new message
+    pub fn prune(&mut self, max_states: usize) -> usize {
+        if max_states == 0 || self.chain.len() <= max_states {
+            return 0;
+        }
+
+        let mut usage: Vec<(i64, String)> = self.chain.iter()
+            .filter(|(word, _)| { !word.is_empty() })
+            .map(|(word, edges)| { (edges.iter().map(|x| { x.1 }).sum(), word.clone()) })
+            .collect();
+        usage.sort();
+
+        let to_remove = self.chain.len() - max_states * 9 / 10;
+        let removed: HashSet<String> = usage.into_iter()
+            .take(to_remove)
+            .map(|x| { x.1 })
+            .collect();
+
+        self.remove_words(&removed);
+        removed.len()
+    }
+
+    // Removes every word that looks like the given one, along with every edge that touches it.
+    // Returns how many words were removed
+    pub fn forget(&mut self, word: &str) -> usize {
+        let word = normalize_word(word);
+        if word.is_empty() {
+            return 0;
+        }
+
+        let removed: HashSet<String> = self.chain.keys()
+            .chain(self.chain.values().flatten().map(|x| { &x.0 }))
+            .filter(|x| { normalize_word(x) == word })
+            .cloned()
+            .collect();
+
+        self.remove_words(&removed);
+        removed.len()
+    }
+
+    fn remove_words(&mut self, words: &HashSet<String>) {
+        if words.is_empty() {
+            return;
+        }
+
+        self.touch();
+        self.chain.retain(|word, _| { !words.contains(word) });
+        for edges in self.chain.values_mut() {
+            edges.retain(|x| { !words.contains(&x.0) });
+        }
+        self.chain.retain(|_, edges| { !edges.is_empty() });
+    }
+}
+
+// The sum of the weights of a word, None if it overflows
+fn state_weight(edges: &[(String, i64)]) -> Option<i64> {
+    edges.iter().try_fold(0i64, |sum, x| { sum.checked_add(x.1) })
+}
+
+// Decides what gets to be learned by the chains, so mistyped commands, links and other
+// bots don't end up in /dao
+#[derive(Debug, PartialEq, Clone)]
+pub struct TrainingFilter {
+    pub ignore_urls: bool,
+    pub ignore_commands: bool,
+    pub ignore_forwards: bool,
+    pub ignore_bots: bool,
+    pub keep_case: bool,
+    pub min_words: usize,
+}
+
+impl Default for TrainingFilter {
+    fn default() -> TrainingFilter {
+        TrainingFilter {
+            ignore_urls: true,
+            ignore_commands: true,
+            ignore_forwards: true,
+            ignore_bots: true,
+            keep_case: true,
+            min_words: 2,
+        }
+    }
+}
+
+fn is_url(word: &str) -> bool {
+    let word = word.to_lowercase();
+    word.starts_with("http://") || word.starts_with("https://") || word.starts_with("www.")
+        || word.starts_with("t.me/") || word.contains("://")
+}
+
+impl TrainingFilter {
+    // Every option can be overwritten with a MARKOV_* environment variable
+    pub fn from_env() -> TrainingFilter {
+        let default = TrainingFilter::default();
+
+        TrainingFilter {
+            ignore_urls: env_flag("MARKOV_IGNORE_URLS", default.ignore_urls),
+            ignore_commands: env_flag("MARKOV_IGNORE_COMMANDS", default.ignore_commands),
+            ignore_forwards: env_flag("MARKOV_IGNORE_FORWARDS", default.ignore_forwards),
+            ignore_bots: env_flag("MARKOV_IGNORE_BOTS", default.ignore_bots),
+            keep_case: env_flag("MARKOV_KEEP_CASE", default.keep_case),
+            min_words: env_number("MARKOV_MIN_WORDS", default.min_words as u64) as usize,
+        }
+    }
+
+    // Returns the text that should be fed to add_sequence, or None if the message
+    // shouldn't be learned at all
+    pub fn prepare(&self, text: &str, forwarded: bool, from_bot: bool) -> Option<String> {
+        if (self.ignore_forwards && forwarded) || (self.ignore_bots && from_bot) {
+            return None;
+        }
+
+        let text = text.trim();
+        if self.ignore_commands && text.starts_with('/') {
+            return None;
+        }
+
+        let words: Vec<&str> = text.split_whitespace()
+            .filter(|x| { !(self.ignore_urls && is_url(x)) })
+            .collect();
+
+        if words.is_empty() || words.len() < self.min_words {
+            return None;
+        }
+
+        let sentence = words.join(" ");
+        if self.keep_case {
+            Some(sentence)
+        } else {
+            Some(sentence.to_lowercase())
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Markov;
+    use super::{Markov, Revision, TrainingFilter};
     use std::collections::HashMap;
     #[test]
     fn building_markov() {
@@ -126,9 +551,32 @@ mod tests {
         hashmap.insert("c".to_string(), vec![("".to_string(), 1)]);
     
         assert_eq!(Markov {
-            chain: hashmap
+            chain: hashmap,
+            last_decay: 0,
+            sentences: 3,
+            revision: Revision::default(),
         }, markov);
     }
+
+    #[test]
+    fn revision_test() {
+        let mut markov = Markov::new();
+        markov.add_sequence("a b".to_string());
+
+        // Copies and reading don't change it, anything that changes the chain does
+        let revision = markov.revision();
+        assert_eq!(markov.clone().revision(), revision);
+        markov.get_random();
+        assert_eq!(markov.forget("c"), 0);
+        assert_eq!(markov.revision(), revision);
+
+        markov.forget("b");
+        assert_ne!(markov.revision(), revision);
+
+        let loaded: Markov = serde_json::from_str(&serde_json::to_string(&markov).unwrap()).unwrap();
+        assert_eq!(loaded, markov);
+        assert_ne!(loaded.revision(), markov.revision());
+    }
     
     #[test]
     fn generate_test() {
@@ -143,5 +591,141 @@ mod tests {
         let mut markov = Markov::new();
         assert_eq!(markov.get_random(), None);
     }
-}
 
+    #[test]
+    fn filter_keeps_case_and_punctuation() {
+        let filter = TrainingFilter::default();
+        assert_eq!(filter.prepare("  Ce faci,   Sacale?\n", false, false),
+            Some("Ce faci, Sacale?".to_string()));
+    }
+
+    #[test]
+    fn filter_skips_commands_forwards_and_bots() {
+        let filter = TrainingFilter::default();
+        assert_eq!(filter.prepare("/adauga prost scris", false, false), None);
+        assert_eq!(filter.prepare("mesaj dat mai departe", true, false), None);
+        assert_eq!(filter.prepare("sunt un bot", false, true), None);
+    }
+
+    #[test]
+    fn filter_strips_urls_and_short_messages() {
+        let filter = TrainingFilter::default();
+        assert_eq!(filter.prepare("uite https://youtube.com/watch aici", false, false),
+            Some("uite aici".to_string()));
+        assert_eq!(filter.prepare("www.google.com", false, false), None);
+        assert_eq!(filter.prepare("da", false, false), None);
+    }
+
+    #[test]
+    fn filter_can_be_relaxed() {
+        let filter = TrainingFilter {
+            ignore_urls: false,
+            ignore_commands: false,
+            ignore_forwards: false,
+            ignore_bots: false,
+            keep_case: false,
+            min_words: 1,
+        };
+        assert_eq!(filter.prepare("/Da HTTP://X.RO", true, true),
+            Some("/da http://x.ro".to_string()));
+    }
+
+    #[test]
+    fn decay_test() {
+        let mut markov = Markov::new();
+        for _ in 0..5 {
+            markov.add_sequence("a b".to_string());
+        }
+        markov.add_sequence("a c".to_string());
+
+        assert!(!markov.decay_if_due(100, 10));
+        assert!(!markov.decay_if_due(105, 10));
+        assert!(markov.decay_if_due(110, 10));
+
+        assert_eq!(markov.chain.get("a"), Some(&vec![("b".to_string(), 3),
+                                                      ("c".to_string(), 1)]));
+        assert_eq!(markov.chain.get(""), Some(&vec![("a".to_string(), 3)]));
+        assert!(!markov.decay_if_due(115, 10));
+        assert!(!markov.decay_if_due(1000, 0));
+    }
+
+    #[test]
+    fn prune_test() {
+        let mut markov = Markov::new();
+        for _ in 0..3 {
+            markov.add_sequence("a b".to_string());
+        }
+        markov.add_sequence("c d e".to_string());
+
+        assert_eq!(markov.prune(10), 0);
+        assert_eq!(markov.prune(4), 3);
+        assert_eq!(markov.get_random(), Some("a b".to_string()));
+    }
+
+    #[test]
+    fn forget_test() {
+        let mut markov = Markov::new();
+        markov.add_sequence("Ce faci Sacale?".to_string());
+
+        assert_eq!(markov.forget("pula"), 0);
+        assert_eq!(markov.forget("sacale"), 1);
+        assert_eq!(markov.get_random(), Some("Ce faci".to_string()));
+
+        assert_eq!(markov.forget("CE"), 1);
+        assert_eq!(markov.get_random(), None);
+    }
+
+    #[test]
+    fn inspect_test() {
+        let mut markov = Markov::new();
+        markov.add_sequence("Ce faci sacale".to_string());
+        markov.add_sequence("ce faci ba".to_string());
+        markov.add_sequence("faci ba".to_string());
+
+        assert_eq!(markov.sentences(), 3);
+        assert_eq!(markov.vocabulary_size(), 5);
+        assert_eq!(markov.edge_count(), 9);
+        assert_eq!(markov.top_words(2), vec![("faci".to_string(), 3),
+                                             ("ba".to_string(), 2)]);
+        assert_eq!(markov.top_transitions(1), vec![("faci".to_string(),
+                                                    "ba".to_string(), 2)]);
+        assert_eq!(markov.next_words("CE"), vec![("faci".to_string(), 1.0)]);
+        assert_eq!(markov.next_words("faci"), vec![("ba".to_string(), 2.0 / 3.0),
+                                                   ("sacale".to_string(), 1.0 / 3.0)]);
+        assert_eq!(markov.next_words("pula"), vec![]);
+        assert!(markov.serialized_size() > 0);
+    }
+
+    #[test]
+    fn edges_test() {
+        let mut markov = Markov::new();
+        markov.add_sequence("a b".to_string());
+        markov.add_sequence("a c".to_string());
+
+        let mut copy = Markov::new();
+        for (from, to, weight) in markov.edges() {
+            copy.add_edge(from.clone(), to.clone(), weight);
+        }
+        copy.sentences = markov.sentences;
+        assert_eq!(copy, markov);
+
+        copy.add_edge("a".to_string(), "b".to_string(), 2);
+        assert_eq!(copy.next_words("a"), vec![("b".to_string(), 0.75),
+                                              ("c".to_string(), 0.25)]);
+
+        copy.merge(&markov);
+        assert_eq!(copy.sentences(), 4);
+        assert_eq!(copy.next_words("a"), vec![("b".to_string(), 4.0 / 6.0),
+                                              ("c".to_string(), 2.0 / 6.0)]);
+
+        // Taking a chain out twice only takes out what's there
+        let mut only_c = Markov::new();
+        only_c.add_sequence("a c".to_string());
+        copy.subtract(&only_c);
+        copy.subtract(&only_c);
+        copy.subtract(&only_c);
+        assert_eq!(copy.next_words("a"), vec![("b".to_string(), 1.0)]);
+        assert_eq!(copy.next_words("c"), vec![]);
+        assert_eq!(copy.sentences(), 1);
+    }
+}
@@ -0,0 +1,293 @@
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use serde::{Serialize, Deserialize};
+
+use crate::aliases::Aliases;
+use crate::constants::*;
+
+const DAY: u64 = 24 * 60 * 60;
+// Thoughts can't be scheduled further than this
+const MAX_DELAY: u64 = 366 * DAY;
+// Longer thoughts are refused, in characters
+pub const MAX_THOUGHT_LENGTH: usize = 1000;
+
+// Which thought /gind reveals
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+pub enum ThoughtOrder {
+    #[default]
+    Random,
+    // The oldest one
+    Fifo,
+    // The newest one
+    Lifo,
+}
+
+impl ThoughtOrder {
+    pub fn from_name(name: &str) -> Option<ThoughtOrder> {
+        match name.trim().to_lowercase().as_str() {
+        "random" => { Some(ThoughtOrder::Random) }
+        "fifo" => { Some(ThoughtOrder::Fifo) }
+        "lifo" => { Some(ThoughtOrder::Lifo) }
+        _ => { None }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+        ThoughtOrder::Random => { "random" }
+        ThoughtOrder::Fifo => { "fifo" }
+        ThoughtOrder::Lifo => { "lifo" }
+        }
+    }
+}
+
+// A thought waiting to be revealed with /gind
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Thought {
+    pub text: String,
+    // 0 when we don't know, for the thoughts from before we kept track
+    pub added_by: i64,
+    pub added_at: u64,
+    // After this the thought is dropped without being revealed
+    pub expires_at: Option<u64>,
+}
+
+impl Thought {
+    // A thought that expires after `lifetime` seconds, or never if it's 0
+    pub fn new(text: String, added_by: i64, now: u64, lifetime: u64) -> Thought {
+        Thought {
+            text,
+            added_by,
+            added_at: now,
+            expires_at: if lifetime == 0 { None } else { Some(now + lifetime) },
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|x| { x <= now })
+    }
+
+    // The anonymous footer shown under the thought, if we know when it was added
+    pub fn age(&self, now: u64) -> Option<String> {
+        if self.added_at == 0 {
+            return None;
+        }
+
+        match now.saturating_sub(self.added_at) / DAY {
+        0 => { Some("un gind de azi".to_string()) }
+        1 => { Some("un gind de ieri".to_string()) }
+        days => { Some(format!("un gind de acum {} zile", days)) }
+        }
+    }
+
+    // The thought as posted on the group, with its age under it
+    pub fn reveal(&self, now: u64) -> String {
+        match self.age(now) {
+        Some(age) => { format!("{}\n\n- {}", self.text, age) }
+        None => { self.text.clone() }
+        }
+    }
+
+    // Whether /gind sterge [what] matches this thought: @username means everything added
+    // by that user, anything else is a piece of the text
+    pub fn matches(&self, what: &str, author: Option<i64>) -> bool {
+        match author {
+        Some(author) => { self.added_by == author }
+        None => { self.text.to_lowercase().contains(&what.trim().to_lowercase()) }
+        }
+    }
+}
+
+// A thought posted on its own when its time comes, instead of waiting for /gind
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ScheduledThought {
+    pub deliver_at: u64,
+    pub thought: Thought,
+}
+
+// When a scheduled thought should be posted: "in 2h", "peste 1h 30m" or a date like
+// "2021-05-01 18:00", in the time zone that is `utc_offset` minutes ahead of UTC. Returns
+// None when the text doesn't look like a time at all, so it can be taken as a thought
+pub fn parse_delivery(spec: &str, now: u64, utc_offset: i64) -> Option<Result<u64, String>> {
+    let spec = spec.trim();
+    let lowercase = spec.to_lowercase();
+
+    let relative = ["in ", "peste "].iter()
+        .find(|x| { lowercase.starts_with(*x) })
+        .map(|x| { &spec[x.len()..] });
+
+    let at = match relative {
+    Some(delay) => {
+        match humantime::parse_duration(delay.trim()) {
+        Ok(delay) => { now.checked_add(delay.as_secs()) }
+        Err(_) => { return Some(Err(format!("Nu inteleg cit inseamna {}", delay.trim()))); }
+        }
+    }
+    None => {
+        let looks_like_date = spec.len() >= 10 && spec.as_bytes()[4] == b'-' &&
+            spec[..4].chars().all(|x| { x.is_ascii_digit() });
+        if !looks_like_date {
+            return None;
+        }
+
+        let full = match spec.len() {
+        10 => { format!("{} 00:00:00", spec) }
+        16 => { format!("{}:00", spec) }
+        _ => { spec.to_string() }
+        };
+
+        match humantime::parse_rfc3339_weak(&full) {
+        Ok(x) => {
+            let utc = x.duration_since(UNIX_EPOCH).map(|x| { x.as_secs() as i64 }).unwrap_or(0);
+            Some((utc - utc_offset * 60).max(0) as u64)
+        }
+        Err(_) => { return Some(Err(format!("Nu inteleg data {}, scrie-o ca 2021-05-01 18:00",
+                                            spec))); }
+        }
+    }
+    };
+
+    Some(match at {
+    Some(at) if at <= now => { Err("Momentul ala a trecut deja".to_string()) }
+    Some(at) if at - now <= MAX_DELAY => { Ok(at) }
+    _ => { Err("Nu tin minte ginduri pentru mai mult de un an".to_string()) }
+    })
+}
+
+// What /gindeste was asked to do
+#[derive(Debug, PartialEq)]
+pub struct GindesteArgs {
+    pub chat_id: i64,
+    pub deliver_at: Option<u64>,
+    pub text: String,
+}
+
+// /gindeste [Alias]~[Cind]~[Mesaj], where the alias (or the id of the chat) and the time
+// are optional. Whether the sender may write on the chat is checked separately
+pub fn parse_gindeste(args: &str, current_chat: i64, aliases: &Aliases, now: u64,
+                      utc_offset: i64) -> Result<GindesteArgs, String> {
+    let target = args.split_once('~').and_then(|(first, rest)| {
+        let first = first.trim();
+        aliases.get(first)
+            .or_else(|| { i64::from_str(first).ok() })
+            .map(|x| { (x, rest) })
+    });
+
+    let (chat_id, rest) = match target {
+    Some(x) => { x }
+    None => { (current_chat, args) }
+    };
+
+    let (deliver_at, text) = match rest.split_once('~') {
+    Some((time, text)) => {
+        match parse_delivery(time, now, utc_offset) {
+        Some(Ok(at)) => { (Some(at), text) }
+        Some(Err(x)) => { return Err(x); }
+        None => { (None, rest) }
+        }
+    }
+    None => { (None, rest) }
+    };
+
+    // Without an alias or a time, a '~' means the alias is wrong
+    if target.is_none() && deliver_at.is_none() && text.contains('~') {
+        return Err(WRONG_ALIAS.to_string());
+    }
+
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(GIND_NO_TEXT.to_string());
+    }
+    if text.chars().count() > MAX_THOUGHT_LENGTH {
+        return Err(format!("Gindul e prea lung, am loc doar de {} caractere", MAX_THOUGHT_LENGTH));
+    }
+
+    Ok(GindesteArgs {
+        chat_id,
+        deliver_at,
+        text: text.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry_test() {
+        let forever = Thought::new("mereu".to_string(), 1, 100, 0);
+        assert!(!forever.is_expired(u64::MAX));
+
+        let short = Thought::new("scurt".to_string(), 1, 100, 50);
+        assert!(!short.is_expired(149));
+        assert!(short.is_expired(150));
+    }
+
+    #[test]
+    fn age_test() {
+        let thought = Thought::new("ceva".to_string(), 1, DAY, 0);
+        assert_eq!(thought.age(DAY + 5), Some("un gind de azi".to_string()));
+        assert_eq!(thought.age(2 * DAY + 5), Some("un gind de ieri".to_string()));
+        assert_eq!(thought.age(10 * DAY), Some("un gind de acum 9 zile".to_string()));
+
+        let old = Thought::new("vechi".to_string(), 0, 0, 0);
+        assert_eq!(old.age(DAY), None);
+    }
+
+    #[test]
+    fn matches_test() {
+        let thought = Thought::new("Ce MAI faci".to_string(), 7, 0, 0);
+        assert!(thought.matches("mai f", None));
+        assert!(!thought.matches("altceva", None));
+        assert!(thought.matches("@ana", Some(7)));
+        assert!(!thought.matches("@ion", Some(8)));
+    }
+
+    #[test]
+    fn delivery_test() {
+        // 2021-05-01 12:00:00 UTC
+        let now = 1619870400;
+
+        assert_eq!(parse_delivery("in 2h", now, 0), Some(Ok(now + 2 * 3600)));
+        assert_eq!(parse_delivery("Peste 1h 30m", now, 0), Some(Ok(now + 5400)));
+        assert_eq!(parse_delivery("2021-05-01 18:00", now, 0), Some(Ok(now + 6 * 3600)));
+        assert_eq!(parse_delivery("2021-05-01 18:00", now, 180), Some(Ok(now + 3 * 3600)));
+        assert_eq!(parse_delivery("2021-05-02", now, 0), Some(Ok(now + 12 * 3600)));
+
+        assert!(matches!(parse_delivery("in doua ore", now, 0), Some(Err(_))));
+        assert!(matches!(parse_delivery("2021-04-30 18:00", now, 0), Some(Err(_))));
+        assert!(matches!(parse_delivery("2021-13-01", now, 0), Some(Err(_))));
+        assert!(matches!(parse_delivery("in 400d", now, 0), Some(Err(_))));
+
+        assert_eq!(parse_delivery("un gind oarecare", now, 0), None);
+        assert_eq!(parse_delivery("2021", now, 0), None);
+    }
+
+    #[test]
+    fn gindeste_test() {
+        let now = 1619870400;
+        let mut aliases = Aliases::new();
+        aliases.insert("Yeet", -469444439);
+        let parse = |args: &str| { parse_gindeste(args, -5, &aliases, now, 0) };
+        let args = |chat_id: i64, deliver_at: Option<u64>, text: &str| {
+            Ok(GindesteArgs { chat_id, deliver_at, text: text.to_string() })
+        };
+
+        assert_eq!(parse(" salut "), args(-5, None, "salut"));
+        assert_eq!(parse("yeet~salut"), args(-469444439, None, "salut"));
+        assert_eq!(parse("YEET~salut"), args(-469444439, None, "salut"));
+        assert_eq!(parse("-100~salut"), args(-100, None, "salut"));
+        assert_eq!(parse("yeet~ce~faci"), args(-469444439, None, "ce~faci"));
+        assert_eq!(parse("yeet~in 2h~salut"), args(-469444439, Some(now + 7200), "salut"));
+        assert_eq!(parse("in 2h~salut~tu"), args(-5, Some(now + 7200), "salut~tu"));
+
+        assert_eq!(parse(""), Err(GIND_NO_TEXT.to_string()));
+        assert_eq!(parse("yeet~  "), Err(GIND_NO_TEXT.to_string()));
+        assert_eq!(parse("yeet~in 2h~"), Err(GIND_NO_TEXT.to_string()));
+        assert_eq!(parse("yet~salut"), Err(WRONG_ALIAS.to_string()));
+        assert!(parse("yeet~in doua ore~salut").is_err());
+        assert!(parse(&"a".repeat(MAX_THOUGHT_LENGTH)).is_ok());
+        assert!(parse(&"a".repeat(MAX_THOUGHT_LENGTH + 1)).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use serde::{Serialize, Deserialize};
+
+// What someone may do on a group. The order matters, every role can do everything the
+// roles before it can
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub enum Role {
+    Banned,
+    #[default]
+    Member,
+    Moderator,
+    Owner,
+}
+
+impl Role {
+    pub fn from_name(name: &str) -> Option<Role> {
+        match name.trim().to_lowercase().as_str() {
+        "banat" | "banned" => { Some(Role::Banned) }
+        "membru" | "member" => { Some(Role::Member) }
+        "moderator" => { Some(Role::Moderator) }
+        "sef" | "owner" => { Some(Role::Owner) }
+        _ => { None }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+        Role::Banned => { "banat" }
+        Role::Member => { "membru" }
+        Role::Moderator => { "moderator" }
+        Role::Owner => { "sef" }
+        }
+    }
+
+    // Whether someone with this role may turn a user with the role `from` into `to`. Only
+    // the owners may make other owners
+    pub fn can_change(&self, from: Role, to: Role) -> bool {
+        match self {
+        Role::Owner => { true }
+        Role::Moderator => { from < Role::Moderator && to < Role::Moderator }
+        _ => { false }
+        }
+    }
+}
+
+// Things that need a role on the group
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Action {
+    AddTrigger,
+    DeleteTrigger,
+    AddThought,
+    Dao,
+    ChangeSettings,
+    ManageRoles,
+    Mute,
+    // Forgetting what someone else taught the bot
+    ForgetUser,
+}
+
+impl Action {
+    // The permission table: the lowest role that may do it
+    pub fn needed_role(&self) -> Role {
+        match self {
+        Action::AddTrigger => { Role::Member }
+        Action::DeleteTrigger => { Role::Moderator }
+        Action::AddThought => { Role::Member }
+        Action::Dao => { Role::Member }
+        Action::ChangeSettings => { Role::Moderator }
+        Action::ManageRoles => { Role::Moderator }
+        Action::Mute => { Role::Moderator }
+        Action::ForgetUser => { Role::Moderator }
+        }
+    }
+
+    pub fn allowed(&self, role: Role) -> bool {
+        role >= self.needed_role()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissions() {
+        assert!(!Action::AddTrigger.allowed(Role::Banned));
+        assert!(Action::AddTrigger.allowed(Role::Member));
+        assert!(!Action::DeleteTrigger.allowed(Role::Member));
+        assert!(Action::DeleteTrigger.allowed(Role::Moderator));
+        assert!(Action::ManageRoles.allowed(Role::Owner));
+    }
+
+    #[test]
+    fn role_changes() {
+        assert!(Role::Owner.can_change(Role::Moderator, Role::Owner));
+        assert!(Role::Moderator.can_change(Role::Member, Role::Banned));
+        assert!(Role::Moderator.can_change(Role::Banned, Role::Member));
+        assert!(!Role::Moderator.can_change(Role::Member, Role::Moderator));
+        assert!(!Role::Moderator.can_change(Role::Moderator, Role::Banned));
+        assert!(!Role::Member.can_change(Role::Banned, Role::Member));
+
+        assert_eq!(Role::from_name("Banned"), Some(Role::Banned));
+        assert_eq!(Role::from_name("sef"), Some(Role::Owner));
+        assert_eq!(Role::from_name("rege"), None);
+    }
+}
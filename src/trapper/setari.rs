@@ -0,0 +1,150 @@
+use serde::{Serialize, Deserialize};
+use crate::trapper::ceva::CevaWeights;
+
+// How the triggers answer: a new message on the chat, or a reply to the message that
+// matched
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ReplyMode {
+    #[default]
+    Message,
+    Reply,
+}
+
+// What a group can change about the bot. New groups get the defaults
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct GroupSettings {
+    // Learn from the messages for /dao
+    pub learn: bool,
+    // Answer to the triggers added with /adauga
+    pub triggers: bool,
+    pub reply_mode: ReplyMode,
+    // Set with /ceva, not with the buttons
+    pub ceva: CevaWeights,
+}
+
+impl Default for GroupSettings {
+    fn default() -> GroupSettings {
+        GroupSettings {
+            learn: true,
+            triggers: true,
+            reply_mode: ReplyMode::default(),
+            ceva: CevaWeights::default(),
+        }
+    }
+}
+
+// The settings by the names /setari knows them
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Setting {
+    Learn,
+    Triggers,
+    ReplyMode,
+}
+
+pub const SETTINGS: &[Setting] = &[Setting::Learn, Setting::Triggers, Setting::ReplyMode];
+
+impl Setting {
+    pub fn from_name(name: &str) -> Option<Setting> {
+        SETTINGS.iter().copied().find(|x| { x.name() == name.trim().to_lowercase() })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+        Setting::Learn => { "invata" }
+        Setting::Triggers => { "comenzi" }
+        Setting::ReplyMode => { "raspuns" }
+        }
+    }
+
+    // How the setting shows up in the /setari menu
+    pub fn label(&self) -> &'static str {
+        match self {
+        Setting::Learn => { "Invat pentru /dao" }
+        Setting::Triggers => { "Raspund la comenzi" }
+        Setting::ReplyMode => { "Raspund cu" }
+        }
+    }
+
+    // The values the setting takes, in the order the button goes through them
+    pub fn values(&self) -> &'static [&'static str] {
+        match self {
+        Setting::Learn | Setting::Triggers => { &["da", "nu"] }
+        Setting::ReplyMode => { &["mesaj", "reply"] }
+        }
+    }
+}
+
+impl GroupSettings {
+    pub fn get(&self, setting: Setting) -> &'static str {
+        match setting {
+        Setting::Learn => { if self.learn { "da" } else { "nu" } }
+        Setting::Triggers => { if self.triggers { "da" } else { "nu" } }
+        Setting::ReplyMode => {
+            match self.reply_mode { ReplyMode::Message => { "mesaj" } ReplyMode::Reply => { "reply" } }
+        }
+        }
+    }
+
+    pub fn set(&mut self, setting: Setting, value: &str) -> Result<(), String> {
+        let value = value.trim().to_lowercase();
+
+        match (setting, value.as_str()) {
+        (Setting::Learn, "da") => { self.learn = true; }
+        (Setting::Learn, "nu") => { self.learn = false; }
+        (Setting::Triggers, "da") => { self.triggers = true; }
+        (Setting::Triggers, "nu") => { self.triggers = false; }
+        (Setting::ReplyMode, "mesaj") => { self.reply_mode = ReplyMode::Message; }
+        (Setting::ReplyMode, "reply") => { self.reply_mode = ReplyMode::Reply; }
+        _ => {
+            return Err(format!("{} poate sa fie doar {}", setting.name(),
+                               setting.values().join(" sau ")));
+        }
+        }
+
+        Ok(())
+    }
+
+    // Moves the setting to its next value, for the buttons
+    pub fn cycle(&mut self, setting: Setting) {
+        let values = setting.values();
+        let current = values.iter().position(|x| { *x == self.get(setting) }).unwrap_or(0);
+        let next = values[(current + 1) % values.len()];
+
+        self.set(setting, next).expect("every value of a setting is valid");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings() {
+        let mut settings = GroupSettings::default();
+        assert!(settings.learn && settings.triggers);
+
+        settings.set(Setting::from_name("Invata").unwrap(), "nu").unwrap();
+        assert!(!settings.learn);
+        assert!(settings.set(Setting::Learn, "poate").is_err());
+
+        settings.cycle(Setting::ReplyMode);
+        assert_eq!(settings.reply_mode, ReplyMode::Reply);
+        settings.cycle(Setting::ReplyMode);
+        assert_eq!(settings.reply_mode, ReplyMode::Message);
+
+        for setting in SETTINGS {
+            for value in setting.values() {
+                settings.set(*setting, value).unwrap();
+                assert_eq!(settings.get(*setting), *value);
+            }
+        }
+
+        // Settings saved before a new one was added get its default, the ones that are gone
+        // are ignored
+        let old: GroupSettings = serde_json::from_str(r#"{"learn": false, "language": "English"}"#)
+            .unwrap();
+        assert_eq!(old, GroupSettings { learn: false, ..GroupSettings::default() });
+        assert_eq!(Setting::from_name("altceva"), None);
+    }
+}
@@ -0,0 +1,344 @@
+use std::convert::Infallible;
+use std::panic::AssertUnwindSafe;
+
+use futures::FutureExt;
+
+use teloxide::prelude::*;
+use teloxide::dispatching::dialogue::GetChatId;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use teloxide::utils::command::BotCommand;
+
+use crate::constants::*;
+use crate::trapper::adauga::Expression;
+use crate::trapper::rol::Action;
+use crate::{ALIASES, BOT_NAME, STATEMAP, BotCommands};
+use crate::{add_command, check_user_member, check_user_permission, find_chat, process_message,
+            UpdateDone};
+
+// The data of the wizard's buttons starts with this
+pub const CALLBACK: &str = "adauga:";
+// Groups on the keyboard of the first step, the others can still be written by alias
+const MAX_GROUPS: usize = 20;
+const MAX_RESPONSE_LENGTH: usize = 1000;
+
+// Where someone is with /adauga in a private chat. Every step keeps what the ones before it
+// chose
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Wizard {
+    #[default]
+    Idle,
+    Group,
+    Expression { group_id: i64 },
+    Response { group_id: i64, expression: String },
+    Confirm { group_id: i64, expression: String, response: String },
+}
+
+// The private messages and the wizard's buttons go through the same dialogue, so a chat
+// handles them in the order they came. Both variants are big, boxing one wouldn't help
+#[allow(clippy::large_enum_variant)]
+pub enum ChatUpdate {
+    Message(Message),
+    Button(CallbackQuery),
+}
+
+impl GetChatId for ChatUpdate {
+    fn chat_id(&self) -> i64 {
+        match self {
+        ChatUpdate::Message(message) => { message.chat.id }
+        ChatUpdate::Button(query) => {
+            query.message.as_ref().map_or(query.from.id, |x| { x.chat.id })
+        }
+        }
+    }
+}
+
+pub type Cx = DialogueWithCx<AutoSend<Bot>, ChatUpdate, Wizard, Infallible>;
+
+pub async fn handle(cx: Cx) -> DialogueStage<Wizard> {
+    let _done = UpdateDone;
+    let DialogueWithCx { cx, dialogue } = cx;
+    let state = match dialogue { Ok(x) => { x } Err(x) => { match x {} } };
+
+    let handled = match cx.update {
+    ChatUpdate::Message(message) => {
+        AssertUnwindSafe(on_message(UpdateWithCx { requester: cx.requester, update: message }, state))
+            .catch_unwind()
+            .await
+    }
+    ChatUpdate::Button(query) => {
+        AssertUnwindSafe(on_button(UpdateWithCx { requester: cx.requester, update: query }, state))
+            .catch_unwind()
+            .await
+    }
+    };
+
+    // A panic would end the task of the chat, and the dialogue dispatcher panics as well on
+    // the next update of the chat. The wizard starts over instead
+    match handled {
+    Ok(state) => { DialogueStage::Next(state) }
+    Err(_) => {
+        log::error!("Handling an update of a private chat panicked");
+        DialogueStage::Next(Wizard::Idle)
+    }
+    }
+}
+
+// Checks the expression of the trigger, the error is what's wrong with it
+pub fn expression_step(group_id: i64, text: &str) -> Result<Wizard, String> {
+    Expression::new(0, group_id, text, String::new())?;
+
+    Ok(Wizard::Response { group_id, expression: text.trim().to_string() })
+}
+
+pub fn response_step(group_id: i64, expression: String, text: &str) -> Result<Wizard, String> {
+    let response = text.trim();
+    if response.is_empty() || response.chars().count() > MAX_RESPONSE_LENGTH {
+        return Err(format!("Raspunsul trebuie sa aiba intre 1 si {} caractere",
+                           MAX_RESPONSE_LENGTH));
+    }
+
+    Ok(Wizard::Confirm { group_id, expression, response: response.to_string() })
+}
+
+async fn on_message(message: UpdateWithCx<AutoSend<Bot>, Message>, state: Wizard) -> Wizard {
+    let text = message.update.text().unwrap_or("").to_string();
+    let command = BotCommands::parse(&text, BOT_NAME.clone());
+
+    // Commands work as usual in the middle of the wizard
+    match (state, command) {
+    (_, Ok(BotCommands::Adauga(args))) if args.trim().is_empty() => {
+        start(&message).await
+    }
+    (Wizard::Idle, _) => {
+        process_message(message).await;
+        Wizard::Idle
+    }
+    (_, Ok(BotCommands::Anuleaza)) => {
+        answer(&message, WIZARD_CANCELLED, None).await;
+        Wizard::Idle
+    }
+    (state, Ok(_)) => {
+        process_message(message).await;
+        state
+    }
+    (state, Err(_)) if text.trim().is_empty() => {
+        answer(&message, WIZARD_ONLY_TEXT, None).await;
+        state
+    }
+    (Wizard::Group, Err(_)) => {
+        let user_id = message.update.from().map_or(message.update.chat.id, |x| { x.id });
+        match choose_group(&message.requester, user_id, &text).await {
+        Ok(group_id) => {
+            answer(&message, WIZARD_EXPRESSION, None).await;
+            Wizard::Expression { group_id }
+        }
+        Err(x) => {
+            answer(&message, &x, None).await;
+            Wizard::Group
+        }
+        }
+    }
+    (Wizard::Expression { group_id }, Err(_)) => {
+        match expression_step(group_id, &text) {
+        Ok(next) => {
+            answer(&message, WIZARD_RESPONSE, None).await;
+            next
+        }
+        Err(x) => {
+            answer(&message, &format!("{}. Mai incearca", x), None).await;
+            Wizard::Expression { group_id }
+        }
+        }
+    }
+    (Wizard::Response { group_id, expression }, Err(_)) => {
+        match response_step(group_id, expression.clone(), &text) {
+        Ok(next) => {
+            let (summary, keyboard) = confirmation(&next);
+            answer(&message, &summary, Some(keyboard)).await;
+            next
+        }
+        Err(x) => {
+            answer(&message, &x, None).await;
+            Wizard::Response { group_id, expression }
+        }
+        }
+    }
+    (state @ Wizard::Confirm { .. }, Err(_)) => {
+        answer(&message, WIZARD_PRESS_BUTTON, None).await;
+        state
+    }
+    }
+}
+
+async fn on_button(query: UpdateWithCx<AutoSend<Bot>, CallbackQuery>, state: Wizard) -> Wizard {
+    let data = query.update.data.clone().unwrap_or_default();
+    let user_id = query.update.from.id;
+    let menu = query.update.message.as_ref().map(|x| { (x.chat.id, x.id) });
+    let button = data.strip_prefix(CALLBACK).unwrap_or("");
+
+    let (state, alert) = match (state, button, menu) {
+    (Wizard::Idle, _, _) | (_, _, None) => {
+        (Wizard::Idle, Some(WIZARD_OLD_BUTTON.to_string()))
+    }
+    (_, "nu", Some(menu)) => {
+        edit(&query, menu, WIZARD_CANCELLED).await;
+        (Wizard::Idle, None)
+    }
+    (Wizard::Group, group, Some(menu)) if group.starts_with("grup:") => {
+        match choose_group(&query.requester, user_id, &group["grup:".len()..]).await {
+        Ok(group_id) => {
+            edit(&query, menu, &format!("Grupul: {}", chat_label(group_id))).await;
+            send(&query, menu.0, WIZARD_EXPRESSION).await;
+            (Wizard::Expression { group_id }, None)
+        }
+        Err(x) => { (Wizard::Group, Some(x)) }
+        }
+    }
+    (Wizard::Confirm { group_id, expression, response }, "da", Some(menu)) => {
+        // The user may have left the group or lost the role since choosing it
+        let added = match may_add_triggers(&query.requester, group_id, user_id).await {
+        Ok(()) => {
+            match Expression::new(user_id, group_id, &expression, response) {
+            Ok(x) => { add_command(x).await }
+            Err(x) => { x }
+            }
+        }
+        Err(x) => { x }
+        };
+
+        edit(&query, menu, &added).await;
+        (Wizard::Idle, None)
+    }
+    (state, _, _) => {
+        (state, Some(WIZARD_OLD_BUTTON.to_string()))
+    }
+    };
+
+    let mut request = query.requester.answer_callback_query(query.update.id.clone());
+    if let Some(alert) = alert {
+        request = request.text(alert).show_alert(true);
+    }
+    request.await.log_on_error().await;
+
+    state
+}
+
+// The first step: a button for every group where the user can add triggers
+async fn start(message: &UpdateWithCx<AutoSend<Bot>, Message>) -> Wizard {
+    let user_id = message.update.from().map_or(message.update.chat.id, |x| { x.id });
+
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = user_groups(&message.requester, user_id).await
+        .into_iter()
+        .map(|group_id| {
+            vec![InlineKeyboardButton::callback(chat_label(group_id),
+                                                format!("{}grup:{}", CALLBACK, group_id))]
+        })
+        .collect();
+    buttons.push(vec![cancel_button()]);
+
+    answer(message, WIZARD_GROUP, Some(InlineKeyboardMarkup::new(buttons))).await;
+    Wizard::Group
+}
+
+// The group written or pressed, if the user may add triggers there
+async fn choose_group(bot: &AutoSend<Bot>, user_id: i64, target: &str) -> Result<i64, String> {
+    let group_id = match target.trim() {
+    "" => { return Err(WRONG_ALIAS.to_string()); }
+    x => { find_chat(x, 0)? }
+    };
+
+    may_add_triggers(bot, group_id, user_id).await?;
+    Ok(group_id)
+}
+
+async fn may_add_triggers(bot: &AutoSend<Bot>, group_id: i64, user_id: i64) -> Result<(), String> {
+    check_user_member(bot, group_id, user_id).await?;
+    check_user_permission(bot, group_id, user_id, Action::AddTrigger).await
+}
+
+// The groups where the bot saw the user and where they still are
+async fn user_groups(bot: &AutoSend<Bot>, user_id: i64) -> Vec<i64> {
+    let mut candidates: Vec<i64> = STATEMAP.lock().unwrap().iter()
+        .filter(|(chat_id, trapper)| { **chat_id < 0 && trapper.knows_user(user_id) })
+        .map(|(chat_id, _)| { *chat_id })
+        .collect();
+    candidates.sort_unstable();
+    candidates.truncate(MAX_GROUPS);
+
+    let mut groups = vec![];
+    for group_id in candidates {
+        if check_user_member(bot, group_id, user_id).await.is_ok() {
+            groups.push(group_id);
+        }
+    }
+    groups
+}
+
+// The first alias of the chat, or its id
+fn chat_label(chat_id: i64) -> String {
+    match ALIASES.lock().unwrap().of_chat(chat_id).first() {
+    Some(x) => { x.to_string() }
+    None => { chat_id.to_string() }
+    }
+}
+
+fn confirmation(state: &Wizard) -> (String, InlineKeyboardMarkup) {
+    let summary = match state {
+    Wizard::Confirm { group_id, expression, response } => {
+        format!("Pe {} cand cineva zice {} raspund {}\nO adaug?", chat_label(*group_id),
+                expression, response)
+    }
+    _ => { String::new() }
+    };
+
+    let keyboard = InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("Adauga".to_string(), format!("{}da", CALLBACK)),
+        cancel_button(),
+    ]]);
+
+    (summary, keyboard)
+}
+
+fn cancel_button() -> InlineKeyboardButton {
+    InlineKeyboardButton::callback("Anuleaza".to_string(), format!("{}nu", CALLBACK))
+}
+
+async fn answer(message: &UpdateWithCx<AutoSend<Bot>, Message>, text: &str,
+                keyboard: Option<InlineKeyboardMarkup>) {
+    match keyboard {
+    Some(keyboard) => {
+        message.answer(text).reply_markup(keyboard).await.log_on_error().await;
+    }
+    None => {
+        message.answer(text).await.log_on_error().await;
+    }
+    }
+}
+
+async fn send(query: &UpdateWithCx<AutoSend<Bot>, CallbackQuery>, chat_id: i64, text: &str) {
+    query.requester.send_message(chat_id, text).await.log_on_error().await;
+}
+
+// Replaces the message with the buttons, so they can't be pressed again
+async fn edit(query: &UpdateWithCx<AutoSend<Bot>, CallbackQuery>, menu: (i64, i32), text: &str) {
+    query.requester.edit_message_text(menu.0, menu.1, text).await.log_on_error().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps() {
+        assert_eq!(expression_step(-5, " ana & (mere | pere) "),
+                   Ok(Wizard::Response { group_id: -5, expression: "ana & (mere | pere)".to_string() }));
+        assert_eq!(expression_step(-5, "ana & (mere"), Err(BAD_PARANTHESES.to_string()));
+        assert!(expression_step(-5, "").is_err());
+
+        assert_eq!(response_step(-5, "ana".to_string(), " mere "),
+                   Ok(Wizard::Confirm { group_id: -5, expression: "ana".to_string(),
+                                        response: "mere".to_string() }));
+        assert!(response_step(-5, "ana".to_string(), "  ").is_err());
+        assert!(response_step(-5, "ana".to_string(), &"a".repeat(MAX_RESPONSE_LENGTH + 1)).is_err());
+    }
+}